@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tauri::{Manager, Window};
+
+use crate::{duplicate, import, route};
+use crate::errors::MaplineError;
+use crate::import::FileImport;
+use crate::report::{ImportReport, RetryFile};
+use crate::track_analysis::TrackAnalysis;
+
+/// Number of files imported at the same time
+const WORKER_COUNT: usize = 4;
+
+/// Stage of the import of a single file
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Parse,
+    Elevation,
+    Write,
+}
+
+/// Sent to the ui as "import_progress" whenever a file enters a new stage
+#[derive(Serialize, Clone, Debug)]
+pub struct ImportProgress {
+    pub done: usize, // number of finished files
+    pub total: usize,
+    pub file: String,
+    pub stage: Stage,
+}
+
+/// Managed by tauri, cancel flags of the running batch imports
+#[derive(Default)]
+pub struct BatchState(pub Mutex<Vec<Arc<AtomicBool>>>);
+
+impl BatchState {
+    /// Cancels all running batch imports. Files that are already being written
+    /// are completed, all other files are skipped.
+    pub fn cancel(&self) {
+        for c in self.0.lock().unwrap().iter() {
+            c.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn register(&self) -> Arc<AtomicBool> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.0.lock().unwrap().push(cancel.clone());
+        cancel
+    }
+
+    fn unregister(&self, cancel: &Arc<AtomicBool>) {
+        self.0.lock().unwrap().retain(|c| !Arc::ptr_eq(c, cancel));
+    }
+}
+
+/// State shared by the workers of one batch import
+struct Batch {
+    queue: Mutex<VecDeque<(PathBuf, Option<Vec<usize>>)>>, // path and the parts to import
+    total: usize,
+    done: AtomicUsize,
+    force: bool,
+    cancel: Arc<AtomicBool>,
+    written: Mutex<Vec<TrackAnalysis>>, // tracks imported by this batch
+    report: Mutex<ImportReport>,
+}
+
+impl Batch {
+    fn progress(&self, window: &Window, path: &PathBuf, stage: Stage) {
+        window.emit("import_progress", ImportProgress {
+            done: self.done.load(Ordering::SeqCst),
+            total: self.total,
+            file: path.to_string_lossy().to_string(),
+            stage,
+        }).ok();
+    }
+
+    fn check_cancelled(&self) -> Result<(), MaplineError> {
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(MaplineError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// Imports the files on a pool of worker threads. Every imported track is sent
+/// as "track_import", followed by the report of all files as "import_report".
+/// With force, duplicates are imported anyway.
+pub fn import(window: Window, paths: Vec<PathBuf>, force: bool) {
+    start(window, paths.into_iter().map(|p| (p, None)).collect(), force);
+}
+
+/// Imports the files of a previous report again, only the given parts of a file
+/// if set (e.g. the duplicates of a file of which the other tracks were imported)
+pub fn retry(window: Window, files: Vec<RetryFile>, force: bool) {
+    start(window, files.into_iter().map(|f| (PathBuf::from(f.path), f.parts)).collect(), force);
+}
+
+fn start(window: Window, files: Vec<(PathBuf, Option<Vec<usize>>)>, force: bool) {
+    std::thread::spawn(move || {
+        let cancel = window.state::<BatchState>().register();
+        let batch = Arc::new(Batch {
+            total: files.len(),
+            queue: Mutex::new(files.into_iter().collect()),
+            done: AtomicUsize::new(0),
+            force,
+            cancel: cancel.clone(),
+            written: Mutex::new(vec![]),
+            report: Mutex::new(ImportReport::default()),
+        });
+        let workers: Vec<_> = (0..WORKER_COUNT.min(batch.total)).map(|_| {
+            let batch = batch.clone();
+            let window = window.clone();
+            std::thread::spawn(move || work(&batch, &window))
+        }).collect();
+        for w in workers {
+            if w.join().is_err() {
+                println!("Import worker panicked");
+            }
+        }
+        window.state::<BatchState>().unregister(&cancel);
+        let report = batch.report.lock().unwrap().clone();
+        window.emit("import_report", report).ok();
+    });
+}
+
+fn work(batch: &Batch, window: &Window) {
+    loop {
+        let (path, parts) = match batch.queue.lock().unwrap().pop_front() {
+            Some(p) => p,
+            None => return,
+        };
+        let result = batch.check_cancelled().and_then(|_| import_file(batch, window, &path, parts.as_deref()));
+        if let Ok(file) = &result {
+            for ta in &file.written { window.emit("track_import", ta).ok(); }
+        }
+        batch.done.fetch_add(1, Ordering::SeqCst);
+        batch.report.lock().unwrap().add(&path.to_string_lossy(), result);
+    }
+}
+
+/// Nothing is written before all tracks of the file are analyzed, so a cancelled
+/// import leaves no track folders behind. Duplicates and tracks without points
+/// are skipped, the other tracks of the file are imported.
+fn import_file(batch: &Batch, window: &Window, path: &PathBuf, parts: Option<&[usize]>) -> Result<FileImport, MaplineError> {
+    batch.progress(window, path, Stage::Parse);
+    let (tracks, planned) = import::read_path(path)?;
+    let (tracks, mut skipped) = import::select(tracks, batch.force, parts);
+
+    batch.progress(window, path, Stage::Elevation);
+    let mut analyzed: Vec<(usize, import::AnalyzedTrack)> = vec![];
+    for (i, t) in tracks {
+        batch.check_cancelled()?;
+        analyzed.push((i, import::analyze(t, None)?));
+    }
+    let routes = route::analyze_all(planned.routes)?;
+    batch.check_cancelled()?;
+
+    batch.progress(window, path, Stage::Write);
+    // another worker may have written the same track since the check above
+    let mut written = batch.written.lock().unwrap();
+    let mut tracks: Vec<import::AnalyzedTrack> = vec![];
+    for (i, t) in analyzed {
+        match duplicate::find_in(&t.gpx, &written) {
+            Some(candidate) if !batch.force => skipped.push((i, MaplineError::TrackAlreadyImported(candidate))),
+            _ => tracks.push(t),
+        }
+    }
+    let (analysis, routes) = import::write_with_planned(tracks, routes, planned.waypoints)?;
+    for r in routes {
+        window.emit("route_import", r).ok();
+    }
+    written.extend(analysis.iter().cloned());
+    Ok(FileImport { written: analysis, skipped })
+}
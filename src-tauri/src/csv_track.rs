@@ -0,0 +1,179 @@
+use chrono::prelude::{DateTime, NaiveDateTime, TimeZone, Utc};
+use geo_types::Point;
+use gpx::{Gpx, Time, Track, TrackSegment, Waypoint};
+use serde::{Serialize, Deserialize};
+use time::OffsetDateTime;
+
+use crate::errors::MaplineError;
+use crate::import::{self, ParsedTrack};
+use crate::importer::{FormatInfo, Importer};
+use crate::settings::Settings;
+
+/// Formats of times without time zone, read as utc
+const TIME_FORMATS: [&str; 3] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%d.%m.%Y %H:%M:%S"];
+
+/// Reads csv files with the column mapping of the settings
+pub struct CsvImporter;
+
+impl Importer for CsvImporter {
+    fn info(&self) -> FormatInfo {
+        FormatInfo { id: "csv", name: "CSV", extensions: &["csv"], column_mapping: true }
+    }
+
+    /// Plain text without magic bytes, only detected by extension
+    fn detect(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<Vec<ParsedTrack>, MaplineError> {
+        let columns = Settings::load().map(|s| s.csv_columns).unwrap_or_default();
+        Ok(import::untyped(vec![read(data, &columns)?]))
+    }
+}
+
+/// Names of the columns to read, stored in the settings. Columns that are not
+/// set are detected by their header name (e.g. "latitude" or "lat").
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CsvColumns {
+    pub lat: Option<String>,
+    pub lon: Option<String>,
+    pub time: Option<String>,
+    pub ele: Option<String>,
+}
+
+/// Reads one point per row, rows without valid coordinates are skipped
+pub fn read(data: &[u8], columns: &CsvColumns) -> Result<Gpx, MaplineError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter(data))
+        .flexible(true)
+        .from_reader(data);
+    let headers: Vec<String> = match reader.headers() {
+        Err(e) => return Err(MaplineError::ParseError(e.to_string())),
+        Ok(h) => h.iter().map(|h| h.trim().to_string()).collect(),
+    };
+    let column = |mapped: &Option<String>, names: &[&str]| match mapped {
+        Some(m) => headers.iter().position(|h| h == m),
+        None => headers.iter().position(|h| names.contains(&h.to_lowercase().as_str())),
+    };
+    let lat_col = column(&columns.lat, &["lat", "latitude"]);
+    let lon_col = column(&columns.lon, &["lon", "lng", "long", "longitude"]);
+    let time_col = column(&columns.time, &["time", "timestamp", "datetime", "date"]);
+    let ele_col = column(&columns.ele, &["ele", "elevation", "alt", "altitude"]);
+    let (lat_col, lon_col) = match (lat_col, lon_col) {
+        (Some(lat), Some(lon)) => (lat, lon),
+        _ => return Err(MaplineError::ParseError("no latitude and longitude columns found".to_string())),
+    };
+
+    let mut segment = TrackSegment::new();
+    for record in reader.records() {
+        let record = match record {
+            Err(_e) => continue,
+            Ok(r) => r,
+        };
+        // decimal commas are used with semicolon separated files
+        let number = |col: usize| record.get(col).and_then(|v| v.trim().replace(',', ".").parse::<f64>().ok());
+        let (lat, lon) = match (number(lat_col), number(lon_col)) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => continue,
+        };
+        let mut w = Waypoint::new(Point::new(lon, lat));
+        w.elevation = ele_col.and_then(number);
+        w.time = time_col.and_then(|c| record.get(c)).and_then(parse_time);
+        segment.points.push(w);
+    }
+    if segment.points.is_empty() {
+        return Err(MaplineError::NoTrackPoints);
+    }
+
+    let mut track = Track::new();
+    track.segments.push(segment);
+    let mut gpx = Gpx::default();
+    gpx.version = gpx::GpxVersion::Gpx11;
+    gpx.tracks.push(track);
+    Ok(gpx)
+}
+
+/// Header names of the csv, used to let the user map the columns
+pub fn headers(data: &[u8]) -> Result<Vec<String>, MaplineError> {
+    let mut reader = csv::ReaderBuilder::new().delimiter(delimiter(data)).from_reader(data);
+    match reader.headers() {
+        Err(e) => Err(MaplineError::ParseError(e.to_string())),
+        Ok(h) => Ok(h.iter().map(|h| h.trim().to_string()).collect()),
+    }
+}
+
+/// Comma, or semicolon or tab if the header line contains more of them
+fn delimiter(data: &[u8]) -> u8 {
+    let header = data.split(|b| *b == b'\n').next().unwrap_or(&[]);
+    let count = |d: u8| header.iter().filter(|b| **b == d).count();
+    [b';', b'\t'].iter()
+        .cloned()
+        .find(|d| count(*d) > count(b','))
+        .unwrap_or(b',')
+}
+
+/// RFC 3339, one of TIME_FORMATS or a unix timestamp in seconds
+fn parse_time(value: &str) -> Option<Time> {
+    let value = value.trim();
+    let timestamp = if let Ok(t) = value.parse::<DateTime<Utc>>() {
+        t.timestamp()
+    } else if let Ok(t) = value.parse::<f64>() {
+        t as i64
+    } else {
+        let naive = TIME_FORMATS.iter().find_map(|f| NaiveDateTime::parse_from_str(value, f).ok())?;
+        Utc.from_utc_datetime(&naive).timestamp()
+    };
+    Some(Time::from(OffsetDateTime::from_unix_timestamp(timestamp).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(gpx: &Gpx) -> &Vec<Waypoint> {
+        &gpx.tracks[0].segments[0].points
+    }
+
+    #[test]
+    fn detects_delimiter_of_header() {
+        assert_eq!(delimiter(b"lat,lon,ele\n1,2,3"), b',');
+        assert_eq!(delimiter(b"lat;lon;ele\n1,5;2,5;3"), b';');
+        assert_eq!(delimiter(b"lat\tlon\tele\n1.5\t2.5\t3"), b'\t');
+        // commas in the rows are not counted
+        assert_eq!(delimiter(b"lat;lon\n1,5;2,5"), b';');
+    }
+
+    #[test]
+    fn reads_comma_separated() {
+        let gpx = read(b"Latitude,Longitude,Altitude,Time\n47.37,8.54,408,2022-08-12 10:00:00\n47.38,8.55,410,2022-08-12T10:00:10\n", &CsvColumns::default()).unwrap();
+        let points = points(&gpx);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].point().y(), 47.37);
+        assert_eq!(points[0].point().x(), 8.54);
+        assert_eq!(points[1].elevation, Some(410.));
+        let duration = OffsetDateTime::from(points[1].time.unwrap()) - OffsetDateTime::from(points[0].time.unwrap());
+        assert_eq!(duration.whole_seconds(), 10);
+    }
+
+    #[test]
+    fn reads_decimal_commas_of_semicolon_separated() {
+        let gpx = read(b"lat;lon;ele\n47,37;8,54;408,5\n", &CsvColumns::default()).unwrap();
+        let points = points(&gpx);
+        assert_eq!(points[0].point().y(), 47.37);
+        assert_eq!(points[0].point().x(), 8.54);
+        assert_eq!(points[0].elevation, Some(408.5));
+    }
+
+    #[test]
+    fn mapped_columns_and_invalid_rows() {
+        let columns = CsvColumns { lat: Some("y".to_string()), lon: Some("x".to_string()), ..CsvColumns::default() };
+        let gpx = read(b"x,y\n8.54,47.37\n,\nabc,47.38\n8.56,47.39\n", &columns).unwrap();
+        assert_eq!(points(&gpx).len(), 2);
+        assert_eq!(points(&gpx)[1].point().x(), 8.56);
+    }
+
+    #[test]
+    fn missing_coordinate_columns() {
+        assert!(matches!(read(b"a,b\n1,2\n", &CsvColumns::default()), Err(MaplineError::ParseError(_))));
+    }
+}
@@ -2,6 +2,7 @@ use gpx::{Gpx, Waypoint};
 use geo::{HaversineDistance};
 
 use crate::pause::Pause;
+use crate::segment;
 /*
 pub fn from_track_analysis(track_analysis: &TrackAnalysis) -> Result<f64, String> {
     let gpx = io::read_gpx(&track_analysis.ulid);
@@ -19,12 +20,13 @@ pub fn from_track_analysis(track_analysis: &TrackAnalysis) -> Result<f64, String
 */
 
 pub fn calculate(gpx: &Gpx, pauses: &Vec<Pause>) -> f64 {
-    let mut last_p: &Waypoint = &gpx.tracks[0].segments[0].points[0].clone();
+    let points = segment::points(gpx);
+    let mut last_p: &Waypoint = points[0];
     let mut dist = 0.0;
 
     let mut pause_pos = 0;
 
-    for (i, p) in gpx.tracks[0].segments[0].points.iter().enumerate() {
+    for (i, p) in points.into_iter().enumerate() {
         // distance after last pause to end
         if pause_pos == pauses.len() {
             dist += last_p.point().haversine_distance(&p.point().into());
@@ -0,0 +1,201 @@
+use chrono::prelude::{DateTime, Utc};
+use geo::{HaversineDistance, Point};
+use gpx::{Gpx, Waypoint};
+use serde::{Serialize, Deserialize};
+use time::OffsetDateTime;
+
+use crate::{index, io, segment};
+use crate::track_analysis::TrackAnalysis;
+
+/// Tracks overlapping in time for at least this share of the shorter track are compared
+const MIN_TIME_OVERLAP: f64 = 0.8;
+/// Share of sampled points that must lie close to the other track
+const MIN_SIMILARITY: f64 = 0.8;
+/// Points within this radius (in meters) of the other track are considered matching
+const MATCH_RADIUS: f64 = 50.;
+/// Number of points sampled per track for the geometry comparison
+const SAMPLE_SIZE: usize = 100;
+
+/// Already imported track that is most likely the same as the imported one
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DuplicateCandidate {
+    pub ulid: String,
+    pub name: Option<String>,
+    pub start_time: Option<String>,
+    pub time_overlap: Option<f64>, // share of the shorter track, None for tracks without time
+    pub similarity: f64, // share of points lying on the other track
+}
+
+/// Searches the library for a track with overlapping time and similar geometry.
+/// Tracks without timestamps are compared by geometry only.
+pub fn find(gpx: &Gpx) -> Option<DuplicateCandidate> {
+    let points = segment::points(gpx);
+    let candidates = match index::duplicate_candidates(bounding_box(&points)?, time_range(&points)) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{:?}", e);
+            index::read_from_disk().into_iter().filter(|ta| ta.trashed.is_none()).collect()
+        }
+    };
+    find_in(gpx, &candidates)
+}
+
+/// Same as find, but only the given tracks are compared
+pub fn find_in(gpx: &Gpx, analysis: &[TrackAnalysis]) -> Option<DuplicateCandidate> {
+    let points = segment::points(gpx);
+    let time_range = time_range(&points);
+    let bbox = bounding_box(&points)?;
+
+    let mut best: Option<DuplicateCandidate> = None;
+    for ta in analysis {
+        if !overlaps(&bbox, ta) {
+            continue;
+        }
+        let time_overlap = match (time_range, analysis_time_range(ta)) {
+            (Some(a), Some(b)) => {
+                let overlap = time_overlap(a, b);
+                if overlap < MIN_TIME_OVERLAP {
+                    continue;
+                }
+                Some(overlap)
+            }
+            (None, None) => None,
+            // tracks with and without time are never duplicates of each other
+            _ => continue,
+        };
+        let other = match io::read_gpx(&ta.ulid) {
+            Some(g) => g,
+            None => continue,
+        };
+        let other_points = segment::points(&other);
+        let similarity = similarity(&points, &other_points).min(similarity(&other_points, &points));
+        if similarity < MIN_SIMILARITY {
+            continue;
+        }
+        if best.as_ref().map(|b| b.similarity < similarity).unwrap_or(true) {
+            best = Some(DuplicateCandidate {
+                ulid: ta.ulid.clone(),
+                name: ta.name.clone(),
+                start_time: ta.start_time.clone(),
+                time_overlap,
+                similarity,
+            });
+        }
+    }
+    best
+}
+
+/// Overlapping time as share of the shorter range
+fn time_overlap(a: (i64, i64), b: (i64, i64)) -> f64 {
+    let shorter = (a.1 - a.0).min(b.1 - b.0);
+    if shorter <= 0 {
+        return if a.0 == b.0 { 1. } else { 0. };
+    }
+    let overlap = a.1.min(b.1) - a.0.max(b.0);
+    (overlap.max(0) as f64) / shorter as f64
+}
+
+/// Unix timestamps of the first and last point
+fn time_range(points: &Vec<&Waypoint>) -> Option<(i64, i64)> {
+    match (points.first().and_then(|p| p.time), points.last().and_then(|p| p.time)) {
+        (Some(s), Some(e)) => Some((OffsetDateTime::from(s).unix_timestamp(), OffsetDateTime::from(e).unix_timestamp())),
+        _ => None,
+    }
+}
+
+pub fn analysis_time_range(ta: &TrackAnalysis) -> Option<(i64, i64)> {
+    let start = ta.start_time.as_ref()?.parse::<DateTime<Utc>>().ok()?;
+    let end = ta.end_time.as_ref()?.parse::<DateTime<Utc>>().ok()?;
+    Some((start.timestamp(), end.timestamp()))
+}
+
+/// Share of sampled points of a lying within MATCH_RADIUS of a point of b
+fn similarity(a: &Vec<&Waypoint>, b: &Vec<&Waypoint>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.;
+    }
+    let step = (a.len() / SAMPLE_SIZE).max(1);
+    let samples: Vec<Point<f64>> = a.iter().step_by(step).map(|w| w.point()).collect();
+    let matching = samples.iter()
+        .filter(|s| b.iter().any(|w| s.haversine_distance(&w.point()) < MATCH_RADIUS))
+        .count();
+    matching as f64 / samples.len() as f64
+}
+
+/// (x_min, x_max, y_min, y_max)
+fn bounding_box(points: &Vec<&Waypoint>) -> Option<(f64, f64, f64, f64)> {
+    let first = points.first()?.point();
+    Some(points.iter().fold((first.x(), first.x(), first.y(), first.y()), |b, w| {
+        let p = w.point();
+        (b.0.min(p.x()), b.1.max(p.x()), b.2.min(p.y()), b.3.max(p.y()))
+    }))
+}
+
+fn overlaps(bbox: &(f64, f64, f64, f64), ta: &TrackAnalysis) -> bool {
+    bbox.0 <= ta.x_max.0 && bbox.1 >= ta.x_min.0 && bbox.2 <= ta.y_max.1 && bbox.3 >= ta.y_min.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(lat: f64, lon_from: f64, count: usize) -> Vec<Waypoint> {
+        // 0.0001 degrees of longitude are about 7.5 m at this latitude
+        (0..count).map(|i| Waypoint::new(Point::new(lon_from + i as f64 * 0.0001, lat))).collect()
+    }
+
+    #[test]
+    fn time_overlap_is_share_of_shorter_track() {
+        assert_eq!(time_overlap((0, 100), (0, 100)), 1.);
+        assert_eq!(time_overlap((0, 100), (20, 70)), 1.);
+        assert_eq!(time_overlap((0, 100), (50, 150)), 0.5);
+        assert_eq!(time_overlap((0, 100), (200, 300)), 0.);
+        // tracks of a single point only overlap if they start at once
+        assert_eq!(time_overlap((10, 10), (10, 10)), 1.);
+        assert_eq!(time_overlap((10, 10), (20, 20)), 0.);
+    }
+
+    #[test]
+    fn same_line_is_similar() {
+        let a = line(47.37, 8.54, 200);
+        let b = line(47.37, 8.54, 200);
+        let (a, b): (Vec<&Waypoint>, Vec<&Waypoint>) = (a.iter().collect(), b.iter().collect());
+        assert_eq!(similarity(&a, &b), 1.);
+    }
+
+    #[test]
+    fn parallel_line_within_radius_is_similar() {
+        // 0.0003 degrees of latitude are about 33 m
+        let a = line(47.37, 8.54, 200);
+        let b = line(47.3703, 8.54, 200);
+        let (a, b): (Vec<&Waypoint>, Vec<&Waypoint>) = (a.iter().collect(), b.iter().collect());
+        assert!(similarity(&a, &b) >= MIN_SIMILARITY);
+    }
+
+    #[test]
+    fn parallel_line_outside_radius_is_not_similar() {
+        // 0.001 degrees of latitude are about 111 m
+        let a = line(47.37, 8.54, 200);
+        let b = line(47.371, 8.54, 200);
+        let (a, b): (Vec<&Waypoint>, Vec<&Waypoint>) = (a.iter().collect(), b.iter().collect());
+        assert_eq!(similarity(&a, &b), 0.);
+    }
+
+    #[test]
+    fn part_of_a_longer_track_is_not_similar_both_ways() {
+        let long = line(47.37, 8.54, 400);
+        let short = line(47.37, 8.54, 100);
+        let (long, short): (Vec<&Waypoint>, Vec<&Waypoint>) = (long.iter().collect(), short.iter().collect());
+        assert_eq!(similarity(&short, &long), 1.);
+        // find_in takes the minimum of both directions
+        assert!(similarity(&long, &short) < MIN_SIMILARITY);
+    }
+
+    #[test]
+    fn empty_track_is_not_similar() {
+        let a = line(47.37, 8.54, 10);
+        let a: Vec<&Waypoint> = a.iter().collect();
+        assert_eq!(similarity(&a, &vec![]), 0.);
+        assert_eq!(similarity(&vec![], &a), 0.);
+    }
+}
@@ -11,14 +11,110 @@ use serde::{Deserialize, Serialize};
 
 use crate::errors::{self, MaplineError};
 use crate::geotiff::TIFFStream;
-use crate::{paths, pause};
+use crate::{paths, pause, segment};
 use crate::io;
 use crate::pause::Pause;
+use crate::sensor::SensorData;
 
 const SRTM_FILE_NAME: &str = "srtm_%lon_%lat.tif";
 const SRTM_ZIPF_NAME: &str = "srtm_%lon_%lat.zip";
 const SRTM_ADDR_NAME: &str = "https://srtm.csi.cgiar.org/wp-content/uploads/files/srtm_5x5/TIFF/srtm_%lon_%lat.zip";
 const MIN_ELE_INTERVAL: f64 = 100.;
+/// Creators (device manufacturers) whose devices record the altitude with a
+/// barometric altimeter, the elevation of their gpx points is used by default
+const BAROMETRIC_CREATORS: [&str; 6] = ["garmin", "suunto", "wahoo", "polar", "coros", "sigma"];
+
+/// Source of the elevation profile and of the elevation stats of a track
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ElevationSource {
+    Barometric, // altitude channel of the device (e.g. fit enhanced_altitude, igc pressure altitude)
+    Gps, // elevation of the gpx points
+    Dem, // digital elevation model (srtm)
+}
+
+/// Elevation profiles of all available sources, for comparison
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ElevationProfiles {
+    pub barometric: Option<Vec<(f64, f64)>>,
+    pub gps: Option<Vec<(f64, f64)>>,
+    pub dem: Option<Vec<(f64, f64)>>,
+}
+
+pub fn elevation_source_from_string(source: &str) -> Option<ElevationSource> {
+    match source.to_lowercase().as_str() {
+        "barometric" => Some(ElevationSource::Barometric),
+        "gps" => Some(ElevationSource::Gps),
+        "dem" => Some(ElevationSource::Dem),
+        _ => None,
+    }
+}
+
+/// Automatic choice: the altitude channel of the device if recorded, the gpx
+/// elevation of devices with a barometric altimeter, otherwise the elevation
+/// model. Flights never use the elevation model (it is the ground, not the
+/// altitude of the glider).
+pub fn default_source(gpx: &Gpx, sensor_data: &SensorData, flight: bool) -> ElevationSource {
+    let has_gps = segment::points(gpx).iter().any(|p| p.elevation.is_some());
+    if sensor_data.altitude.iter().any(|a| a.is_some()) {
+        return ElevationSource::Barometric;
+    }
+    let creator = gpx.creator.clone().unwrap_or_default().to_lowercase();
+    if has_gps && BAROMETRIC_CREATORS.iter().any(|c| creator.contains(c)) {
+        return ElevationSource::Barometric;
+    }
+    if has_gps && flight {
+        return ElevationSource::Gps;
+    }
+    ElevationSource::Dem
+}
+
+/// Altitude of every point recorded by the device for the source, None for the
+/// elevation model. Barometric falls back to the gpx elevation, as devices with
+/// an altimeter write the barometric altitude to the points.
+pub fn device_altitudes(source: ElevationSource, gpx: &Gpx, sensor_data: &SensorData) -> Option<Vec<Option<f64>>> {
+    let gps = || segment::points(gpx).iter().map(|p| p.elevation).collect();
+    match source {
+        ElevationSource::Barometric if sensor_data.altitude.iter().any(|a| a.is_some()) => Some(sensor_data.altitude.clone()),
+        ElevationSource::Barometric | ElevationSource::Gps => Some(gps()),
+        ElevationSource::Dem => None,
+    }
+}
+
+/// Elevation profile of the source, in the format of from_latlong
+pub fn from_source(source: ElevationSource, gpx: &Gpx, sensor_data: &SensorData, pauses: &Vec<Pause>) -> Result<(Vec<(f64, f64)>, f64, f64, f64, f64, Vec<(f64, f64)>), errors::MaplineError> {
+    match device_altitudes(source, gpx, sensor_data) {
+        Some(a) => from_device(gpx, &a),
+        None => from_latlong(gpx, pauses),
+    }
+}
+
+/// True if the points have an elevation of their own. Fit files have no gps
+/// altitude, their points get the altitude channel of the device, which is the
+/// barometric source.
+pub fn has_gps_channel(gpx: &Gpx, sensor_data: &SensorData) -> bool {
+    let gps: Vec<Option<f64>> = segment::points(gpx).iter().map(|p| p.elevation).collect();
+    gps.iter().any(|e| e.is_some()) && gps != sensor_data.altitude
+}
+
+/// Profiles of the device channels and the elevation model. The barometric
+/// profile is only returned if the device recorded a separate altitude channel,
+/// the gps profile only if it is not a copy of that channel.
+pub fn profiles(gpx: &Gpx, sensor_data: &SensorData, pauses: &Vec<Pause>) -> ElevationProfiles {
+    let barometric = match sensor_data.altitude.iter().any(|a| a.is_some()) {
+        true => from_device(gpx, &sensor_data.altitude).ok(),
+        false => None,
+    };
+    let gps = match has_gps_channel(gpx, sensor_data) {
+        true => from_source(ElevationSource::Gps, gpx, sensor_data, pauses).ok(),
+        false => None,
+    };
+    ElevationProfiles {
+        barometric: barometric.map(|p| p.0),
+        gps: gps.map(|p| p.0),
+        dem: from_latlong(gpx, pauses).ok().map(|p| p.0),
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct Location {
@@ -53,7 +149,8 @@ pub fn from_latlong(gpx: &Gpx, pauses: &Vec<Pause>) -> Result<(Vec<(f64, f64)>,
     let mut current_distance = 0.;
     let mut interval_distance = MIN_ELE_INTERVAL;
 
-    let mut last_point = &gpx.tracks[0].segments[0].points[0].clone();
+    let points = segment::points(gpx);
+    let mut last_point = points[0];
     let mut last_ele: Option<f64> = None;
     let mut current_ele: f64;
 
@@ -63,8 +160,8 @@ pub fn from_latlong(gpx: &Gpx, pauses: &Vec<Pause>) -> Result<(Vec<(f64, f64)>,
     let mut pause_pos = 0;
 
     let mut i = 0;
-    println!("tracks: {}, segments: {}, points: {}", &gpx.tracks.len(), &gpx.tracks[0].segments.len(), &gpx.tracks[0].segments[0].points.len());
-    for  w in &gpx.tracks[0].segments[0].points {
+    println!("tracks: {}, segments: {}, points: {}", &gpx.tracks.len(), gpx.tracks.iter().map(|t| t.segments.len()).sum::<usize>(), points.len());
+    for w in points.iter().copied() {
         // 0.00042 corresponds to half a pixel of the elevation map
         // TODO: check if this is correct (at edges of the map, on tiles with lat < 0)
         if pause_pos == pauses.len() || i < pauses.get(pause_pos).unwrap().index_before || i == pauses.get(pause_pos).unwrap().index_before {
@@ -94,7 +191,7 @@ pub fn from_latlong(gpx: &Gpx, pauses: &Vec<Pause>) -> Result<(Vec<(f64, f64)>,
             last_point = w;
 
             // only check every nth point to reduce array size
-            if interval_distance >= MIN_ELE_INTERVAL || &w == &gpx.tracks[0].segments[0].points.last().unwrap() {
+            if interval_distance >= MIN_ELE_INTERVAL || w == *points.last().unwrap() {
                 elevation_coords.push(w.point().x_y());
 
                 interval_distance = 0.;
@@ -172,6 +269,47 @@ pub fn from_latlong(gpx: &Gpx, pauses: &Vec<Pause>) -> Result<(Vec<(f64, f64)>,
         i += 1;
     }
 
+    Ok(profile(result, elevation_coords))
+
+    // Err(errors::MaplineError::CouldNotLoadElevation)
+}
+
+/// Elevation profile from the altitudes recorded by the device (one per point
+/// of the gpx), in the same format as from_latlong
+pub fn from_device(gpx: &Gpx, altitudes: &[Option<f64>]) -> Result<(Vec<(f64, f64)>, f64, f64, f64, f64, Vec<(f64, f64)>), errors::MaplineError> {
+    let points = segment::points(gpx);
+    let mut result: Vec<(f64, f64)> = vec![];
+    let mut elevation_coords: Vec<(f64, f64)> = vec![];
+    let mut current_distance = 0.;
+    let mut interval_distance = MIN_ELE_INTERVAL;
+    let mut last_point = match points.first() {
+        Some(p) => *p,
+        None => return Err(MaplineError::CouldNotLoadElevation),
+    };
+    for (i, w) in points.iter().copied().enumerate() {
+        let d = last_point.point().haversine_distance(&w.point());
+        current_distance += d;
+        interval_distance += d;
+        last_point = w;
+        let ele = match altitudes.get(i).cloned().flatten() {
+            Some(e) => e,
+            None => continue,
+        };
+        // only check every nth point to reduce array size
+        if interval_distance >= MIN_ELE_INTERVAL || i == points.len() - 1 {
+            interval_distance = 0.;
+            elevation_coords.push(w.point().x_y());
+            result.push((current_distance / 1000., ele));
+        }
+    }
+    if result.is_empty() {
+        return Err(MaplineError::CouldNotLoadElevation);
+    }
+    Ok(profile(result, elevation_coords))
+}
+
+/// Smooths the elevation profile and calculates gain, loss, max and min
+fn profile(result: Vec<(f64, f64)>, elevation_coords: Vec<(f64, f64)>) -> (Vec<(f64, f64)>, f64, f64, f64, f64, Vec<(f64, f64)>) {
     // smoothed elevation test
     let mut smoothed: Vec<(f64, f64)> = result.iter().map(|(x, y)| {
         (x.to_owned(), y.to_owned())
@@ -179,7 +317,7 @@ pub fn from_latlong(gpx: &Gpx, pauses: &Vec<Pause>) -> Result<(Vec<(f64, f64)>,
     if result.len() < 3 {
         println!("Not enough data points for profile smoothing.");
     }
-    for i in 3..result.len()-3 {
+    for i in 3..result.len().saturating_sub(3) {
         smoothed[i].1 = (- 2. * result[i-3].1 + 3. * result[i-2].1 + 6. * result[i-1].1 + 7. * result[i].1 + 6. * result[i+1].1 + 3. * result[i+2].1 - 2. * result[i+3].1) / 21.;
         if smoothed[i].1 < 0. {
             smoothed[i].1 = 0.;
@@ -190,7 +328,7 @@ pub fn from_latlong(gpx: &Gpx, pauses: &Vec<Pause>) -> Result<(Vec<(f64, f64)>,
     let mut ele_max = -414.; // lowest point on earth
     let mut ele_min = 8849.; // highest point on earth
 
-    let mut last_ele = smoothed[0].1;
+    let mut last_ele = smoothed.first().map(|p| p.1).unwrap_or(0.);
     for p in &smoothed {
 
         if p.1 > ele_max { ele_max = p.1; }
@@ -205,9 +343,7 @@ pub fn from_latlong(gpx: &Gpx, pauses: &Vec<Pause>) -> Result<(Vec<(f64, f64)>,
     }
     println!("up_smoothed: {}, down_smoothed: {}", ele_gain, ele_loss);
 
-    Ok((smoothed, ele_gain, ele_loss, ele_max, ele_min, elevation_coords))
-
-    // Err(errors::MaplineError::CouldNotLoadElevation)
+    (smoothed, ele_gain, ele_loss, ele_max, ele_min, elevation_coords)
 }
 
 /// Gets the tile from the srtm directory. If tile not present, tries to load
@@ -228,10 +364,19 @@ fn load_tile(lon: u8, lat: u8, tiles: &mut HashMap<(u8, u8), TIFFStream>) -> Res
     path.push(file_name.replace("%lon", lon_str.as_str()).replace("%lat", lat_str.as_str()));
     if !std::path::Path::new(&path).exists() {
         let address = SRTM_ADDR_NAME.replace("%lon", lon_str.as_str()).replace("%lat", lat_str.as_str());
+        // imports run in parallel, so the tile is downloaded to a file of its own
+        // and moved in place when complete
+        let download_id = ulid::Ulid::new().to_string();
         let mut zip_path = paths::srtm();
-        zip_path.push(SRTM_ZIPF_NAME.replace("%lon", lon_str.as_str()).replace("%lat", lat_str.as_str()));
+        zip_path.push(SRTM_ZIPF_NAME.replace("%lon", lon_str.as_str()).replace("%lat", lat_str.as_str()) + "." + &download_id);
+        let mut download_path = path.clone();
+        download_path.set_extension(format!("tif.{}", download_id));
         println!("address: {}", address);
-        io::download_tiff_zip(&address, PathBuf::from(&zip_path), PathBuf::from(&path));
+        io::download_tiff_zip(&address, PathBuf::from(&zip_path), PathBuf::from(&download_path));
+        std::fs::remove_file(&zip_path).ok();
+        if let Err(e) = std::fs::rename(&download_path, &path) {
+            println!("Could not move tile {:?}: {:?}", download_path, e);
+        }
     }
     match TIFFStream::open(path.to_str().unwrap()) {
         Ok(t) => {
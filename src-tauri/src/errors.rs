@@ -1,7 +1,20 @@
+use crate::duplicate::DuplicateCandidate;
+
 #[derive(Debug)]
 pub enum MaplineError {
     FitFileNotAnActivity,
     ImportError(String),
-    TrackAlreadyImported,
+    FileError(String), // file could not be read
+    ParseError(String), // content does not match the format
+    WriteError(String), // track files could not be written
+    TrackAlreadyImported(DuplicateCandidate),
     CouldNotLoadElevation,
+    NoTrackPoints,
+    UnsupportedFormat,
+    ArchiveError(String),
+    Cancelled,
+    IndexError(String), // library index could not be read or written
+    NothingToUndo,
+    NothingToRedo,
+    LibraryError(String), // library could not be added, switched or moved
 }
\ No newline at end of file
@@ -0,0 +1,367 @@
+use chrono::prelude::{DateTime, Utc};
+use fitparser::{profile, FitDataField, FitDataRecord};
+use geo_types::Point;
+use gpx::{Gpx, Time, Track, TrackSegment, Waypoint};
+use std::io::{Cursor, Read};
+use time::OffsetDateTime;
+
+use crate::errors::MaplineError;
+use crate::import::{Extras, ParsedTrack};
+use crate::importer::{FormatInfo, Importer};
+use crate::lap::{self, Lap};
+use crate::multisport::{LegInfo, UntrackedLeg};
+use crate::sensor::{SensorData, SensorRecord};
+use crate::summary::DeviceSummary;
+use crate::track_analysis::Activity;
+
+/// Magic bytes at offset 8 of the file header
+const FIT_MAGIC: &[u8; 4] = b".FIT";
+
+pub struct FitImporter;
+
+impl Importer for FitImporter {
+    fn info(&self) -> FormatInfo {
+        FormatInfo { id: "fit", name: "FIT", extensions: &["fit"], column_mapping: false }
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        data.get(8..12) == Some(&FIT_MAGIC[..])
+    }
+
+    /// Multisport files are imported as one track per leg
+    fn parse(&self, data: &[u8]) -> Result<Vec<ParsedTrack>, MaplineError> {
+        parse_fit(Cursor::new(data))
+    }
+}
+
+/// Session message of a fit file. Multisport files contain one per leg.
+struct FitSession {
+    start: i64,
+    end: i64,
+    sport: Option<String>,
+    summary: DeviceSummary,
+}
+
+fn parse_fit<R: Read>(mut reader: R) -> Result<Vec<ParsedTrack>, MaplineError> {
+    // import creator and add to gpx
+    let mut activity: Activity = Activity::Other;
+    let mut creator: String = "unknown".to_string();
+    let parsed_fit = match fitparser::from_reader(&mut reader) {
+        Err(err) => return Err(MaplineError::ParseError(err.to_string())),
+        Ok(pf) => pf,
+    };
+    
+    let mut points: Vec<(Waypoint, SensorRecord)> = vec![];
+    let mut sessions: Vec<FitSession> = vec![];
+    let mut fit_laps: Vec<(i64, i64, Lap)> = vec![];
+    for data in parsed_fit {
+        if data.kind() == profile::MesgNum::Record {
+            let mut lat: Option<f64> = None;
+            let mut long: Option<f64> = None;
+            let mut timestamp: Option<DateTime<Utc>> = None;
+            let mut record = SensorRecord::default();
+            let mut altitude: Option<f64> = None;
+            let mut speed: Option<f64> = None;
+            // println!("{:#?}", data);
+            for f in data.fields() {
+                match f.name() {
+                    "position_lat" => lat = Some(f.value().to_string().parse::<f64>().unwrap() * 0.000000083819032),
+                    "position_long" => long = Some(f.value().to_string().parse::<f64>().unwrap() * 0.000000083819032),
+                    "timestamp" => timestamp = Some(f.value().to_string().parse::<DateTime<Utc>>().unwrap()),
+                    "heart_rate" => record.heart_rate = fit_value(f),
+                    "cadence" => record.cadence = fit_value(f),
+                    "power" => record.power = fit_value(f),
+                    "temperature" => record.temperature = fit_value(f),
+                    "enhanced_speed" => record.speed = fit_value(f),
+                    "enhanced_altitude" => record.altitude = fit_value(f),
+                    // older devices only write the non-enhanced fields
+                    "speed" => speed = fit_value(f),
+                    "altitude" => altitude = fit_value(f),
+                    _ => (),
+                }
+            }
+            record.speed = record.speed.or(speed);
+            record.altitude = record.altitude.or(altitude);
+            match (lat, long, timestamp) {
+                (Some(la), Some(lo), Some(ti)) => {
+                    let mut point = Waypoint::new(Point::new(lo, la));
+                    point.time = Some(Time::from(OffsetDateTime::from_unix_timestamp(ti.timestamp()).unwrap()));
+                    point.elevation = record.altitude;
+                    points.push((point, record));
+                }
+                _ => (),
+            }
+        }
+        
+        else if data.kind() == profile::MesgNum::FileId {
+            println!("{:?}", data);
+            for f in data.fields() {
+                match f.name() {
+                    "type" => {
+                        if f.value().to_string() != "activity" {
+                            return Err(MaplineError::FitFileNotAnActivity);
+                        }
+                    }
+                    "manufacturer" => creator = f.value().to_string(),
+                    _ => break,
+                }
+            }
+        }
+        
+        else if data.kind() == profile::MesgNum::Session {
+            if let Some(start) = fit_time(&data, "start_time") {
+                let start = start.timestamp();
+                let end = match (fit_field(&data, "total_elapsed_time"), fit_time(&data, "timestamp")) {
+                    (Some(elapsed), _) => start + elapsed as i64,
+                    (None, Some(t)) => t.timestamp(),
+                    (None, None) => i64::MAX,
+                };
+                let sport = data.fields().iter()
+                    .find(|f| f.name() == "sport")
+                    .map(|f| f.value().to_string());
+                sessions.push(FitSession { start, end, sport, summary: fit_session(&data) });
+            }
+        }
+
+        else if data.kind() == profile::MesgNum::Lap {
+            let start = fit_time(&data, "start_time");
+            let end = fit_time(&data, "timestamp");
+            if let (Some(s), Some(e)) = (start, end) {
+                fit_laps.push((s.timestamp(), e.timestamp(), fit_lap(&data, &s)));
+            }
+        }
+
+        else if data.kind() == profile::MesgNum::Sport {
+            for f in data.fields() {
+                match f.name() {
+                    "sport" => activity = activity_from_fit_sport(&f.value().to_string()),
+                    _ => (),
+                }
+            }
+        }
+
+    }
+
+    // multisport files are split into one track per leg, transitions are not imported as tracks
+    let legs: Vec<&FitSession> = sessions.iter()
+        .filter(|s| s.sport.as_deref() != Some("transition"))
+        .collect();
+    if legs.len() <= 1 {
+        let (waypoints, records): (Vec<Waypoint>, Vec<SensorRecord>) = points.into_iter().unzip();
+        let gpx = fit_gpx(waypoints, &creator);
+        let mut sensor_data = SensorData::default();
+        records.into_iter().for_each(|r| sensor_data.push(r));
+        let laps = fit_laps_of(&gpx, &fit_laps, i64::MIN, i64::MAX);
+        let device_summary = sessions.into_iter().next().map(|s| s.summary);
+        return Ok(vec![ParsedTrack { gpx, activity: Some(activity), extras: Extras { sensor_data, laps, device_summary, leg: None, flight: None }, source: None }]);
+    }
+
+    Ok(split_legs(&legs, &points, &fit_laps, &creator))
+}
+
+/// One track per leg of a multisport session. Legs without gps points (e.g. pool
+/// swimming) can not be imported as track, they are kept in the event of the others.
+fn split_legs(legs: &[&FitSession], points: &[(Waypoint, SensorRecord)], fit_laps: &Vec<(i64, i64, Lap)>, creator: &String) -> Vec<ParsedTrack> {
+    let transition_sec = |i: usize| match legs.get(i + 1) {
+        Some(next) => (next.start - legs[i].end).max(0) as u64,
+        None => 0,
+    };
+    let activity_of = |s: &FitSession| s.sport.as_deref().map(activity_from_fit_sport).unwrap_or(Activity::Other);
+
+    let mut tracked: Vec<(usize, Vec<Waypoint>, SensorData)> = vec![];
+    let mut untracked: Vec<UntrackedLeg> = vec![];
+    for (i, s) in legs.iter().enumerate() {
+        let mut waypoints: Vec<Waypoint> = vec![];
+        let mut sensor_data = SensorData::default();
+        for (w, r) in points.iter() {
+            let t = OffsetDateTime::from(w.time.unwrap()).unix_timestamp();
+            if t >= s.start && t <= s.end {
+                waypoints.push(w.clone());
+                sensor_data.push(r.clone());
+            }
+        }
+        if waypoints.is_empty() {
+            untracked.push(UntrackedLeg {
+                position: i,
+                activity: activity_of(s),
+                start_time: time_string(s.start),
+                end_time: time_string(s.end),
+                summary: s.summary.clone(),
+                transition_sec: transition_sec(i),
+            });
+        } else {
+            tracked.push((i, waypoints, sensor_data));
+        }
+    }
+
+    tracked.into_iter().map(|(i, waypoints, sensor_data)| {
+        let s = legs[i];
+        let gpx = fit_gpx(waypoints, creator);
+        let laps = fit_laps_of(&gpx, fit_laps, s.start, s.end);
+        let leg = LegInfo { position: i, transition_sec: transition_sec(i), untracked: untracked.clone() };
+        ParsedTrack {
+            gpx,
+            activity: Some(activity_of(s)),
+            extras: Extras { sensor_data, laps, device_summary: Some(s.summary.clone()), leg: Some(leg), flight: None },
+            source: None,
+        }
+    }).collect()
+}
+
+/// Unix timestamp as stored in the analysis (rfc 3339)
+fn time_string(timestamp: i64) -> Option<String> {
+    Time::from(OffsetDateTime::from_unix_timestamp(timestamp).ok()?).format().ok()
+}
+
+fn fit_gpx(waypoints: Vec<Waypoint>, creator: &String) -> Gpx {
+    let mut track_segment = TrackSegment::new();
+    track_segment.points = waypoints;
+    let mut track = Track::new();
+    track.segments.push(track_segment);
+    let mut gpx = Gpx::default();
+    gpx.tracks.push(track);
+    gpx.version = gpx::GpxVersion::Gpx11;
+    gpx.creator = Some(creator.clone());
+    gpx
+}
+
+/// Laps starting between start and end with the indices of the points in gpx
+fn fit_laps_of(gpx: &Gpx, fit_laps: &Vec<(i64, i64, Lap)>, start: i64, end: i64) -> Vec<Lap> {
+    fit_laps.iter()
+        .filter(|(s, _, _)| *s >= start && *s <= end)
+        .map(|(s, e, l)| {
+            let mut l = l.clone();
+            let range = lap::index_range(gpx, *s, *e);
+            l.start_index = range.map(|r| r.0);
+            l.end_index = range.map(|r| r.1);
+            l
+        }).collect()
+}
+
+fn activity_from_fit_sport(sport: &str) -> Activity {
+    // TODO: extend for more activities
+    match sport {
+        "xcskiing" | "cross_country_skiing" => Activity::XCSkiing, // has to be tested with xc capable device
+        "cycling" => Activity::Cycling,
+        "running" => Activity::Running,
+        "hiking" => Activity::Hiking,
+        "walking" => Activity::Hiking, // same activity as hiking
+        "swimming" => Activity::Swimming, // has to be tested with swimming capable device
+        "inline_skating" => Activity::InlineSkating, // has to be tested with is capable device
+        "flying" | "hang_gliding" => Activity::Flying,
+        _ => Activity::Other,
+    }
+}
+
+fn fit_field(data: &FitDataRecord, name: &str) -> Option<f64> {
+    data.fields().iter().find(|f| f.name() == name).and_then(fit_value)
+}
+
+fn fit_time(data: &FitDataRecord, name: &str) -> Option<DateTime<Utc>> {
+    data.fields().iter()
+        .find(|f| f.name() == name)
+        .and_then(|f| f.value().to_string().parse::<DateTime<Utc>>().ok())
+}
+
+/// Converts a speed in m/s to kph
+fn kph(speed: Option<f64>) -> Option<f64> {
+    speed.map(|s| s * 3.6)
+}
+
+fn fit_session(data: &FitDataRecord) -> DeviceSummary {
+    DeviceSummary {
+        time_moving: fit_field(data, "total_timer_time").map(|t| t as u64),
+        time_total: fit_field(data, "total_elapsed_time").map(|t| t as u64),
+        distance: fit_field(data, "total_distance"),
+        avg_vel: kph(fit_field(data, "enhanced_avg_speed").or(fit_field(data, "avg_speed"))),
+        max_vel: kph(fit_field(data, "enhanced_max_speed").or(fit_field(data, "max_speed"))),
+        ele_gain: fit_field(data, "total_ascent"),
+        ele_loss: fit_field(data, "total_descent"),
+        avg_heart_rate: fit_field(data, "avg_heart_rate"),
+        max_heart_rate: fit_field(data, "max_heart_rate"),
+        avg_cadence: fit_field(data, "avg_cadence"),
+        avg_power: fit_field(data, "avg_power"),
+        calories: fit_field(data, "total_calories"),
+    }
+}
+
+/// Indices are set once all records are read
+fn fit_lap(data: &FitDataRecord, start_time: &DateTime<Utc>) -> Lap {
+    let start_time = Time::from(OffsetDateTime::from_unix_timestamp(start_time.timestamp()).unwrap());
+    Lap {
+        start_index: None,
+        end_index: None,
+        start_time: start_time.format().ok(),
+        duration_sec: fit_field(data, "total_timer_time"),
+        distance: fit_field(data, "total_distance"),
+        avg_vel: kph(fit_field(data, "enhanced_avg_speed").or(fit_field(data, "avg_speed"))),
+        avg_heart_rate: fit_field(data, "avg_heart_rate"),
+        avg_cadence: fit_field(data, "avg_cadence"),
+        avg_power: fit_field(data, "avg_power"),
+    }
+}
+
+/// Numeric value of a fit field (already scaled by the fit profile)
+fn fit_value(field: &FitDataField) -> Option<f64> {
+    field.value().to_string().parse::<f64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(start: i64, end: i64, sport: &str) -> FitSession {
+        FitSession {
+            start,
+            end,
+            sport: Some(sport.to_string()),
+            summary: DeviceSummary { distance: Some(1500.), time_total: Some((end - start) as u64), ..DeviceSummary::default() },
+        }
+    }
+
+    fn point(t: i64) -> (Waypoint, SensorRecord) {
+        let mut w = Waypoint::new(Point::new(8.54, 47.37));
+        w.time = Some(Time::from(OffsetDateTime::from_unix_timestamp(t).unwrap()));
+        (w, SensorRecord::default())
+    }
+
+    #[test]
+    fn pool_swim_is_kept_as_untracked_leg() {
+        // transition sessions are already filtered out by parse_fit
+        let sessions = vec![session(0, 1000, "swimming"), session(1100, 5000, "cycling"), session(5200, 7000, "running")];
+        let legs: Vec<&FitSession> = sessions.iter().collect();
+        let points: Vec<(Waypoint, SensorRecord)> = (1100..=5000).step_by(100)
+            .chain((5200..=7000).step_by(100))
+            .map(point)
+            .collect();
+
+        let tracks = split_legs(&legs, &points, &vec![], &"test".to_string());
+        assert_eq!(tracks.len(), 2);
+        assert!(matches!(tracks[0].activity, Some(Activity::Cycling)));
+        assert!(matches!(tracks[1].activity, Some(Activity::Running)));
+
+        let bike = tracks[0].extras.leg.as_ref().unwrap();
+        assert_eq!(bike.position, 1);
+        assert_eq!(bike.transition_sec, 200);
+        let run = tracks[1].extras.leg.as_ref().unwrap();
+        assert_eq!(run.position, 2);
+        assert_eq!(run.transition_sec, 0);
+
+        // the swim and the transition after it are part of every leg
+        for leg in [bike, run] {
+            assert_eq!(leg.untracked.len(), 1);
+            let swim = &leg.untracked[0];
+            assert_eq!(swim.position, 0);
+            assert_eq!(swim.transition_sec, 100);
+            assert!(matches!(swim.activity, Activity::Swimming));
+            assert_eq!(swim.summary.distance, Some(1500.));
+            assert_eq!(swim.summary.time_total, Some(1000));
+        }
+    }
+
+    #[test]
+    fn leg_times_are_rfc3339() {
+        assert_eq!(time_string(0).as_deref(), Some("1970-01-01T00:00:00Z"));
+        assert_eq!(time_string(i64::MAX), None);
+    }
+}
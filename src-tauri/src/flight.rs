@@ -0,0 +1,78 @@
+use geo::HaversineDistance;
+use gpx::Gpx;
+use serde::{Serialize, Deserialize};
+use time::OffsetDateTime;
+
+use crate::segment;
+use crate::sensor::SensorData;
+
+/// Minimal time span for the climb rate, shorter spans are too noisy
+const CLIMB_WINDOW_SEC: i64 = 10;
+
+/// Header of a flight log (e.g. igc H-records)
+#[derive(Clone, Debug, Default)]
+pub struct FlightHeader {
+    pub pilot: Option<String>,
+    pub glider: Option<String>,
+}
+
+/// Flight specific stats, stored in the analysis of flying tracks
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FlightSummary {
+    pub pilot: Option<String>,
+    pub glider: Option<String>,
+    pub pressure_altitude: bool, // false if the gnss altitude is used
+    pub max_altitude: Option<f64>, // in meters
+    pub max_climb_rate: Option<f64>, // in m/s
+    pub max_launch_distance: f64, // max distance from the launch point in meters
+}
+
+/// Altitude of every point. The pressure altitude of the sensor data is used if
+/// recorded, otherwise the gnss altitude (elevation of the gpx points).
+pub fn altitudes(gpx: &Gpx, sensor_data: &SensorData) -> Vec<Option<f64>> {
+    if sensor_data.altitude.iter().any(|a| a.is_some()) {
+        return sensor_data.altitude.clone();
+    }
+    segment::points(gpx).iter().map(|p| p.elevation).collect()
+}
+
+pub fn summary(gpx: &Gpx, sensor_data: &SensorData, header: &FlightHeader) -> FlightSummary {
+    let points = segment::points(gpx);
+    let altitudes = altitudes(gpx, sensor_data);
+    let max_altitude = altitudes.iter().flatten().cloned().reduce(f64::max);
+
+    // (unix timestamp, altitude) of all points with both values
+    let timed: Vec<(i64, f64)> = points.iter().zip(altitudes.iter())
+        .filter_map(|(p, a)| Some((OffsetDateTime::from(p.time?).unix_timestamp(), (*a)?)))
+        .collect();
+    let mut max_climb_rate: Option<f64> = None;
+    let mut j = 0;
+    for i in 0..timed.len() {
+        while j < timed.len() && timed[j].0 - timed[i].0 < CLIMB_WINDOW_SEC {
+            j += 1;
+        }
+        if j == timed.len() {
+            break;
+        }
+        let rate = (timed[j].1 - timed[i].1) / (timed[j].0 - timed[i].0) as f64;
+        if max_climb_rate.map_or(true, |m| rate > m) {
+            max_climb_rate = Some(rate);
+        }
+    }
+
+    let max_launch_distance = match points.first() {
+        Some(launch) => points.iter()
+            .map(|p| launch.point().haversine_distance(&p.point()))
+            .fold(0., f64::max),
+        None => 0.,
+    };
+
+    FlightSummary {
+        pilot: header.pilot.clone(),
+        glider: header.glider.clone(),
+        pressure_altitude: sensor_data.altitude.iter().any(|a| a.is_some()),
+        max_altitude,
+        max_climb_rate,
+        max_launch_distance,
+    }
+}
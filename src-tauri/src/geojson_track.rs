@@ -0,0 +1,222 @@
+use chrono::prelude::{DateTime, Utc};
+use geo_types::Point;
+use geojson::{Feature, GeoJson, JsonObject, JsonValue, Value};
+use gpx::{Gpx, Time, Track, TrackSegment, Waypoint};
+use time::OffsetDateTime;
+
+use crate::errors::MaplineError;
+use crate::import::{self, ParsedTrack};
+use crate::importer::{FormatInfo, Importer};
+use crate::route::Planned;
+
+pub struct GeoJsonImporter;
+
+impl Importer for GeoJsonImporter {
+    fn info(&self) -> FormatInfo {
+        FormatInfo { id: "geojson", name: "GeoJSON", extensions: &["geojson"], column_mapping: false }
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        let head = String::from_utf8_lossy(&data[..data.len().min(1024)]);
+        head.trim_start().starts_with('{') && head.contains("\"type\"")
+            && (head.contains("Feature") || head.contains("LineString"))
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<Vec<ParsedTrack>, MaplineError> {
+        Ok(self.parse_with_planned(data)?.0)
+    }
+
+    /// Lines without "coordTimes" (and points without time) are planned routes
+    fn parse_with_planned(&self, data: &[u8]) -> Result<(Vec<ParsedTrack>, Planned), MaplineError> {
+        let (gpxs, planned) = Planned::split_untimed(read(data)?);
+        Ok((import::untyped(gpxs), planned))
+    }
+}
+
+/// Every LineString or MultiLineString feature is read as separate gpx. Point
+/// features are combined to one track, ordered by time if all points have one.
+/// Times of lines are read from the "coordTimes" property (as written by
+/// togeojson and many gps tools).
+pub fn read(data: &[u8]) -> Result<Vec<Gpx>, MaplineError> {
+    let geojson = match String::from_utf8_lossy(data).parse::<GeoJson>() {
+        Err(e) => return Err(MaplineError::ParseError(e.to_string())),
+        Ok(g) => g,
+    };
+    let features: Vec<Feature> = match geojson {
+        GeoJson::FeatureCollection(c) => c.features,
+        GeoJson::Feature(f) => vec![f],
+        GeoJson::Geometry(g) => vec![Feature { bbox: None, geometry: Some(g), id: None, properties: None, foreign_members: None }],
+    };
+
+    let mut result: Vec<Gpx> = vec![];
+    let mut points: Vec<Waypoint> = vec![];
+    for f in features {
+        let properties = f.properties.unwrap_or_default();
+        let geometry = match f.geometry {
+            Some(g) => g,
+            None => continue,
+        };
+        match geometry.value {
+            Value::Point(coord) => {
+                if let Some(mut w) = waypoint(&coord, time_property(&properties)) {
+                    w.elevation = w.elevation.or_else(|| elevation_property(&properties));
+                    points.push(w);
+                }
+            }
+            Value::LineString(coords) => {
+                let times = properties.get("coordTimes").map(|t| vec![t.clone()]);
+                result.push(track_gpx(vec![coords], times, &properties));
+            }
+            Value::MultiLineString(lines) => {
+                let times = match properties.get("coordTimes") {
+                    Some(JsonValue::Array(t)) => Some(t.clone()),
+                    _ => None,
+                };
+                result.push(track_gpx(lines, times, &properties));
+            }
+            _ => (),
+        }
+    }
+    if !points.is_empty() {
+        if points.iter().all(|p| p.time.is_some()) {
+            points.sort_by_key(|p| p.time.map(OffsetDateTime::from));
+        }
+        let mut segment = TrackSegment::new();
+        segment.points = points;
+        let mut track = Track::new();
+        track.segments.push(segment);
+        result.push(gpx_of(track));
+    }
+    result.retain(|g| g.tracks.iter().any(|t| t.segments.iter().any(|s| !s.points.is_empty())));
+    if result.is_empty() {
+        return Err(MaplineError::NoTrackPoints);
+    }
+    Ok(result)
+}
+
+/// One segment per line, times contains one array of timestamps per line
+fn track_gpx(lines: Vec<Vec<Vec<f64>>>, times: Option<Vec<JsonValue>>, properties: &JsonObject) -> Gpx {
+    let mut track = Track::new();
+    track.name = string_property(properties, &["name", "title"]);
+    track.description = string_property(properties, &["desc", "description"]);
+    for (i, line) in lines.iter().enumerate() {
+        let line_times = match times.as_ref().and_then(|t| t.get(i)) {
+            Some(JsonValue::Array(t)) => t.clone(),
+            _ => vec![],
+        };
+        let mut segment = TrackSegment::new();
+        for (j, coord) in line.iter().enumerate() {
+            let time = line_times.get(j).and_then(parse_time);
+            if let Some(w) = waypoint(coord, time) {
+                segment.points.push(w);
+            }
+        }
+        track.segments.push(segment);
+    }
+    gpx_of(track)
+}
+
+fn gpx_of(track: Track) -> Gpx {
+    let mut gpx = Gpx::default();
+    gpx.version = gpx::GpxVersion::Gpx11;
+    gpx.tracks.push(track);
+    gpx
+}
+
+/// Position is [lon, lat] or [lon, lat, ele]
+fn waypoint(coord: &[f64], time: Option<Time>) -> Option<Waypoint> {
+    if coord.len() < 2 {
+        return None;
+    }
+    let mut w = Waypoint::new(Point::new(coord[0], coord[1]));
+    w.elevation = coord.get(2).cloned();
+    w.time = time;
+    Some(w)
+}
+
+fn time_property(properties: &JsonObject) -> Option<Time> {
+    ["time", "timestamp", "datetime"].iter()
+        .find_map(|k| properties.get(*k))
+        .and_then(parse_time)
+}
+
+fn elevation_property(properties: &JsonObject) -> Option<f64> {
+    ["ele", "elevation", "altitude"].iter()
+        .find_map(|k| properties.get(*k))
+        .and_then(|v| v.as_f64())
+}
+
+fn string_property(properties: &JsonObject, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|k| properties.get(*k))
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string())
+}
+
+/// RFC 3339 strings or unix timestamps in seconds
+fn parse_time(value: &JsonValue) -> Option<Time> {
+    let timestamp = match value {
+        JsonValue::String(s) => s.parse::<DateTime<Utc>>().ok()?.timestamp(),
+        JsonValue::Number(n) => n.as_f64()? as i64,
+        _ => return None,
+    };
+    Some(Time::from(OffsetDateTime::from_unix_timestamp(timestamp).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix(w: &Waypoint) -> Option<i64> {
+        w.time.map(|t| OffsetDateTime::from(t).unix_timestamp())
+    }
+
+    #[test]
+    fn line_string_with_coord_times() {
+        let data = br#"{"type": "Feature", "properties": {"name": "Morning Ride", "coordTimes": ["2022-08-12T10:00:00Z", "2022-08-12T10:00:10Z"]},
+            "geometry": {"type": "LineString", "coordinates": [[8.54, 47.37, 408], [8.55, 47.38]]}}"#;
+        let gpxs = read(data).unwrap();
+        assert_eq!(gpxs.len(), 1);
+        let track = &gpxs[0].tracks[0];
+        assert_eq!(track.name, Some("Morning Ride".to_string()));
+        let points = &track.segments[0].points;
+        assert_eq!(points[0].elevation, Some(408.));
+        assert_eq!(points[1].elevation, None);
+        assert_eq!(unix(&points[1]).unwrap() - unix(&points[0]).unwrap(), 10);
+    }
+
+    #[test]
+    fn multi_line_string_has_times_per_line() {
+        let data = br#"{"type": "Feature", "properties": {"coordTimes": [[1660298400, 1660298410], [1660298500]]},
+            "geometry": {"type": "MultiLineString", "coordinates": [[[8.54, 47.37], [8.55, 47.38]], [[8.56, 47.39]]]}}"#;
+        let gpxs = read(data).unwrap();
+        let segments = &gpxs[0].tracks[0].segments;
+        assert_eq!(segments.len(), 2);
+        assert_eq!(unix(&segments[0].points[1]), Some(1660298410));
+        assert_eq!(unix(&segments[1].points[0]), Some(1660298500));
+    }
+
+    #[test]
+    fn line_string_without_coord_times_has_no_time() {
+        let data = br#"{"type": "LineString", "coordinates": [[8.54, 47.37], [8.55, 47.38]]}"#;
+        let gpxs = read(data).unwrap();
+        assert!(gpxs[0].tracks[0].segments[0].points.iter().all(|p| p.time.is_none()));
+    }
+
+    #[test]
+    fn points_are_ordered_by_time() {
+        let data = br#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "properties": {"time": "2022-08-12T10:00:10Z", "ele": 410}, "geometry": {"type": "Point", "coordinates": [8.55, 47.38]}},
+            {"type": "Feature", "properties": {"time": "2022-08-12T10:00:00Z"}, "geometry": {"type": "Point", "coordinates": [8.54, 47.37]}}]}"#;
+        let gpxs = read(data).unwrap();
+        let points = &gpxs[0].tracks[0].segments[0].points;
+        assert_eq!(points[0].point().x(), 8.54);
+        assert_eq!(points[1].elevation, Some(410.));
+    }
+
+    #[test]
+    fn no_lines_or_points() {
+        let data = br#"{"type": "FeatureCollection", "features": []}"#;
+        assert!(matches!(read(data), Err(MaplineError::NoTrackPoints)));
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::prelude::Utc;
+use serde::{Serialize, Deserialize};
+use ulid::Ulid;
+
+use crate::{io, paths, trash};
+use crate::errors::MaplineError;
+
+/// Number of operations kept per track, older ones are dropped
+const MAX_ENTRIES: usize = 30;
+
+/// Folders of an entry, with the track files before the operation and, once
+/// the operation is undone, after it
+const BEFORE: &str = "before";
+const AFTER: &str = "after";
+
+/// Operation that changed the files of a track
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Operation {
+    Edit, // name or activity
+    Recalculate,
+    ElevationSource,
+    Reimport,
+    Tags,
+    SummarySource,
+    Route,
+    Join { parts: Vec<String> }, // the track was joined from the parts, which were trashed
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JournalEntry {
+    pub id: String,
+    pub time: i64, // unix timestamp
+    pub operation: Operation,
+}
+
+/// Content of history/journal.json. Entries before position can be undone,
+/// entries from position on were undone and can be redone.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Journal {
+    pub version: i32,
+    pub entries: Vec<JournalEntry>,
+    pub position: usize,
+}
+
+impl Journal {
+    fn new() -> Journal {
+        Journal {
+            version: crate::JOURNAL_VERSION,
+            entries: vec![],
+            position: 0,
+        }
+    }
+}
+
+/// Journal of the track, empty for tracks without edits
+pub fn journal(ulid: &str) -> Result<Journal, MaplineError> {
+    let path = paths::track_journal(ulid);
+    if !path.exists() {
+        return Ok(Journal::new());
+    }
+    let json_string = fs::read_to_string(path).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    serde_json::from_str(&json_string).map_err(|e| MaplineError::ParseError(e.to_string()))
+}
+
+fn write_journal(ulid: &str, journal: &Journal) -> Result<(), MaplineError> {
+    let content = serde_json::to_string(journal).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    io::write_atomic(&paths::track_journal(ulid), content.as_bytes()).map_err(|e| MaplineError::WriteError(e.to_string()))
+}
+
+/// Saves the current state of the track before the operation. Operations that
+/// were undone can not be redone afterwards.
+pub fn record(ulid: &String, operation: Operation) -> Result<String, MaplineError> {
+    let mut journal = journal(ulid)?;
+    let id = Ulid::new().to_string();
+    if !matches!(operation, Operation::Join { .. }) {
+        save(ulid, &id, BEFORE)?;
+    }
+    for e in journal.entries.drain(journal.position..) {
+        fs::remove_dir_all(entry_dir(ulid, &e.id)).ok();
+    }
+    journal.entries.push(JournalEntry { id: id.clone(), time: Utc::now().timestamp(), operation });
+    if journal.entries.len() > MAX_ENTRIES {
+        let dropped = journal.entries.len() - MAX_ENTRIES;
+        for e in journal.entries.drain(..dropped) {
+            fs::remove_dir_all(entry_dir(ulid, &e.id)).ok();
+        }
+    }
+    journal.position = journal.entries.len();
+    write_journal(ulid, &journal)?;
+    Ok(id)
+}
+
+/// Records the operation and runs it, the record is dropped again if it fails
+pub fn edit<T>(ulid: &String, operation: Operation, f: impl FnOnce() -> Result<T, MaplineError>) -> Result<T, MaplineError> {
+    let id = record(ulid, operation)?;
+    let result = f();
+    if result.is_err() {
+        discard(ulid, &id)?;
+    }
+    result
+}
+
+fn discard(ulid: &str, id: &str) -> Result<(), MaplineError> {
+    let mut journal = journal(ulid)?;
+    if journal.entries.last().map(|e| e.id.as_str()) == Some(id) {
+        journal.entries.pop();
+        journal.position = journal.entries.len();
+        fs::remove_dir_all(entry_dir(ulid, id)).ok();
+        write_journal(ulid, &journal)?;
+    }
+    Ok(())
+}
+
+/// The track and the parts it was joined from, undo and redo change all of them
+pub fn related(ulid: &String) -> Vec<String> {
+    let mut ulids = vec![ulid.clone()];
+    if let Ok(journal) = journal(ulid) {
+        for e in journal.entries {
+            if let Operation::Join { parts } = e.operation {
+                ulids.extend(parts);
+            }
+        }
+    }
+    ulids
+}
+
+/// Brings the track back to its state before the latest operation
+pub fn undo(ulid: &String) -> Result<Journal, MaplineError> {
+    let mut journal = journal(ulid)?;
+    if journal.position == 0 {
+        return Err(MaplineError::NothingToUndo);
+    }
+    let entry = journal.entries[journal.position - 1].clone();
+    match &entry.operation {
+        Operation::Join { parts } => {
+            check_parts(parts)?;
+            for p in parts {
+                trash::restore(p)?;
+            }
+            trash::trash(ulid)?;
+        }
+        _ => {
+            save(ulid, &entry.id, AFTER)?;
+            restore(ulid, &entry.id, BEFORE)?;
+        }
+    }
+    journal.position -= 1;
+    write_journal(ulid, &journal)?;
+    Ok(journal)
+}
+
+/// Applies the latest undone operation again
+pub fn redo(ulid: &String) -> Result<Journal, MaplineError> {
+    let mut journal = journal(ulid)?;
+    if journal.position == journal.entries.len() {
+        return Err(MaplineError::NothingToRedo);
+    }
+    let entry = journal.entries[journal.position].clone();
+    match &entry.operation {
+        Operation::Join { parts } => {
+            check_parts(parts)?;
+            for p in parts {
+                trash::trash(p)?;
+            }
+            trash::restore(ulid)?;
+        }
+        _ => restore(ulid, &entry.id, AFTER)?,
+    }
+    journal.position += 1;
+    write_journal(ulid, &journal)?;
+    Ok(journal)
+}
+
+/// A join is only undone or redone while all its parts are in the library, a
+/// purged part leaves the joined track and the other parts untouched
+fn check_parts(parts: &[String]) -> Result<(), MaplineError> {
+    match parts.iter().find(|p| !paths::track_analysis(p).exists()) {
+        Some(p) => Err(MaplineError::FileError(format!("part {} of the join was purged", p))),
+        None => Ok(()),
+    }
+}
+
+fn entry_dir(ulid: &str, id: &str) -> PathBuf {
+    let mut dir = paths::track_history(ulid);
+    dir.push(id);
+    dir
+}
+
+/// Files of the track folder changed by the operations, the notes and the
+/// original file are never changed
+fn track_files(ulid: &str) -> Vec<PathBuf> {
+    vec![
+        paths::track_analysis(ulid),
+        paths::track_gpx(ulid),
+        paths::track_geojson(ulid),
+        paths::track_elevation(ulid),
+        paths::track_sensors(ulid),
+        paths::track_laps(ulid),
+    ]
+}
+
+/// Multisport event of the track, its leg summary changes with a reimport.
+/// It is shared with the other legs, so it is saved as multisport.json.
+fn event_file(ulid: &String) -> Option<PathBuf> {
+    let ta = io::read_track_analysis(ulid).ok()?;
+    Some(paths::multisport_event(&ta.multisport?))
+}
+
+const EVENT_FILE: &str = "multisport.json";
+
+fn save(ulid: &String, id: &str, state: &str) -> Result<(), MaplineError> {
+    let mut dir = entry_dir(ulid, id);
+    dir.push(state);
+    fs::create_dir_all(&dir).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    let write_error = |e: std::io::Error| MaplineError::WriteError(e.to_string());
+    for path in track_files(ulid).iter().filter(|p| p.exists()) {
+        fs::copy(path, dir.join(path.file_name().unwrap())).map_err(write_error)?;
+    }
+    if let Some(event) = event_file(ulid).filter(|p| p.exists()) {
+        fs::copy(event, dir.join(EVENT_FILE)).map_err(write_error)?;
+    }
+    Ok(())
+}
+
+/// Writes the saved files back, files that did not exist in the saved state
+/// are removed. The archive and trash state is not part of the edits, it is
+/// kept as it is.
+fn restore(ulid: &String, id: &str, state: &str) -> Result<(), MaplineError> {
+    let current = io::read_track_analysis(ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    let mut dir = entry_dir(ulid, id);
+    dir.push(state);
+    let write_error = |e: std::io::Error| MaplineError::WriteError(e.to_string());
+    let read_saved = |saved: &PathBuf| fs::read(saved).map_err(|e| MaplineError::FileError(e.to_string()));
+    io::begin_write(ulid).map_err(write_error)?;
+    for path in track_files(ulid) {
+        let saved = dir.join(path.file_name().unwrap());
+        if saved.exists() {
+            io::write_atomic(&path, &read_saved(&saved)?).map_err(write_error)?;
+        } else if path.exists() {
+            fs::remove_file(&path).map_err(write_error)?;
+        }
+    }
+    let mut ta = io::read_track_analysis(ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    ta.archived = current.archived;
+    ta.trashed = current.trashed;
+    io::write_track_analysis(&ta).map_err(write_error)?;
+    let saved_event = dir.join(EVENT_FILE);
+    if let (Some(event), true) = (event_file(ulid), saved_event.exists()) {
+        io::write_atomic(&event, &read_saved(&saved_event)?).map_err(write_error)?;
+    }
+    io::end_write(ulid).map_err(write_error)?;
+    Ok(())
+}
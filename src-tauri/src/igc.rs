@@ -0,0 +1,238 @@
+use std::io::{Cursor, Read};
+
+use geo_types::Point;
+use gpx::{Gpx, Time, Track, TrackSegment, Waypoint};
+use time::{Date, Month, OffsetDateTime};
+
+use crate::errors::MaplineError;
+use crate::flight::FlightHeader;
+use crate::import::{Extras, ParsedTrack};
+use crate::importer::{FormatInfo, Importer};
+use crate::sensor::{SensorData, SensorRecord};
+use crate::track_analysis::Activity;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+pub struct IgcImporter;
+
+impl Importer for IgcImporter {
+    fn info(&self) -> FormatInfo {
+        FormatInfo { id: "igc", name: "IGC", extensions: &["igc"], column_mapping: false }
+    }
+
+    /// Files start with the A-record (manufacturer of the flight recorder)
+    fn detect(&self, data: &[u8]) -> bool {
+        data.first() == Some(&b'A') && data.windows(2).any(|w| w == b"\nB")
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<Vec<ParsedTrack>, MaplineError> {
+        let flight = read(Cursor::new(data))?;
+        let mut sensor_data = SensorData::default();
+        for altitude in flight.pressure_altitude {
+            sensor_data.push(SensorRecord { altitude, ..Default::default() });
+        }
+        Ok(vec![ParsedTrack {
+            gpx: flight.gpx,
+            activity: Some(Activity::Flying),
+            extras: Extras { sensor_data, flight: Some(flight.header), ..Default::default() },
+            source: None,
+        }])
+    }
+}
+
+/// Flight of an igc file. The gnss altitude is stored as elevation of the gpx
+/// points, the pressure altitude separately (one value per point).
+pub struct IgcFlight {
+    pub gpx: Gpx,
+    pub pressure_altitude: Vec<Option<f64>>,
+    pub header: FlightHeader,
+}
+
+/// Reads the fixes (B-records) and the pilot, glider and date of the header
+/// (H-records). Other records are ignored.
+pub fn read<R: Read>(mut reader: R) -> Result<IgcFlight, MaplineError> {
+    let mut data: Vec<u8> = vec![];
+    if let Err(e) = reader.read_to_end(&mut data) {
+        return Err(MaplineError::ParseError(e.to_string()));
+    }
+    // names in the header are not always utf-8
+    let content = String::from_utf8_lossy(&data);
+
+    let mut header = FlightHeader::default();
+    let mut date: Option<Date> = None;
+    let mut creator: Option<String> = None;
+    let mut segment = TrackSegment::new();
+    let mut pressure_altitude: Vec<Option<f64>> = vec![];
+    let mut day_offset = 0;
+    let mut last_seconds: Option<i64> = None;
+
+    for line in content.lines().map(|l| l.trim_end()) {
+        if line.starts_with('H') {
+            let value = header_value(line);
+            match line.get(2..5) {
+                Some("DTE") => date = parse_date(&value),
+                Some("PLT") if !value.is_empty() => header.pilot = Some(value),
+                Some("GTY") if !value.is_empty() => header.glider = Some(value),
+                Some("FTY") if !value.is_empty() => creator = Some(value),
+                _ => (),
+            }
+        } else if line.starts_with('B') && line.len() >= 35 && line.is_ascii() {
+            let seconds = match parse_seconds(&line[1..7]) {
+                Some(s) => s,
+                None => continue,
+            };
+            // fixes are in utc, the date of the header is the date of the first fix
+            if last_seconds.map(|l| seconds < l).unwrap_or(false) {
+                day_offset += SECONDS_PER_DAY;
+            }
+            last_seconds = Some(seconds);
+            let (lat, lon) = match (parse_coord(&line[7..15], 2), parse_coord(&line[15..24], 3)) {
+                (Some(lat), Some(lon)) => (lat, lon),
+                _ => continue,
+            };
+            let mut w = Waypoint::new(Point::new(lon, lat));
+            w.time = date.and_then(|d| time_of(d, seconds + day_offset));
+            // fixes without 3d fix (validity V) have a gps altitude of 0, 0 is
+            // a valid altitude otherwise (e.g. at the coast)
+            let no_fix = &line[24..25] == "V";
+            w.elevation = line[30..35].parse::<f64>().ok().filter(|a| !(no_fix && *a == 0.));
+            pressure_altitude.push(line[25..30].parse::<f64>().ok());
+            segment.points.push(w);
+        }
+    }
+    if segment.points.is_empty() {
+        return Err(MaplineError::NoTrackPoints);
+    }
+    // recorders without a pressure sensor write 0 for every fix
+    if pressure_altitude.iter().all(|a| a.map(|a| a == 0.).unwrap_or(true)) {
+        pressure_altitude = vec![None; pressure_altitude.len()];
+    }
+
+    let mut track = Track::new();
+    track.segments.push(segment);
+    let mut gpx = Gpx::default();
+    gpx.version = gpx::GpxVersion::Gpx11;
+    gpx.creator = creator;
+    gpx.tracks.push(track);
+    Ok(IgcFlight { gpx, pressure_altitude, header })
+}
+
+/// Value of a header record, e.g. "John Doe" for "HFPLTPILOTINCHARGE:John Doe".
+/// Old files have no long name and colon (e.g. "HFDTE120822").
+fn header_value(line: &str) -> String {
+    match line.split_once(':') {
+        Some((_, v)) => v.trim().to_string(),
+        None => line.get(5..).unwrap_or("").trim().to_string(),
+    }
+}
+
+/// Parses DDMMYY, newer files append the flight number (e.g. "120822,01")
+fn parse_date(value: &str) -> Option<Date> {
+    let value = value.split(',').next()?;
+    let day = value.get(0..2)?.parse::<u8>().ok()?;
+    let month = value.get(2..4)?.parse::<u8>().ok()?;
+    let year = value.get(4..6)?.parse::<i32>().ok()?;
+    Date::from_calendar_date(2000 + year, Month::try_from(month).ok()?, day).ok()
+}
+
+/// Seconds since midnight of HHMMSS
+fn parse_seconds(value: &str) -> Option<i64> {
+    let h = value[0..2].parse::<i64>().ok()?;
+    let m = value[2..4].parse::<i64>().ok()?;
+    let s = value[4..6].parse::<i64>().ok()?;
+    Some(h * 3600 + m * 60 + s)
+}
+
+/// Parses DDMMmmmN (latitude, 2 degree digits) or DDDMMmmmE (longitude, 3 degree digits)
+fn parse_coord(value: &str, degree_digits: usize) -> Option<f64> {
+    let degrees = value[..degree_digits].parse::<f64>().ok()?;
+    let minutes = value[degree_digits..degree_digits + 5].parse::<f64>().ok()? / 1000.;
+    let coord = degrees + minutes / 60.;
+    match &value[degree_digits + 5..] {
+        "N" | "E" => Some(coord),
+        "S" | "W" => Some(-coord),
+        _ => None,
+    }
+}
+
+fn time_of(date: Date, seconds: i64) -> Option<Time> {
+    let timestamp = date.midnight().assume_utc().unix_timestamp() + seconds;
+    Some(Time::from(OffsetDateTime::from_unix_timestamp(timestamp).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(w: &Waypoint) -> i64 {
+        OffsetDateTime::from(w.time.unwrap()).unix_timestamp()
+    }
+
+    #[test]
+    fn reads_header_and_fixes() {
+        let igc = "AXXX001\n\
+            HFDTEDATE:120822,01\n\
+            HFPLTPILOTINCHARGE:John Doe\n\
+            HFGTYGLIDERTYPE:Ventus 2\n\
+            HFFTYFRTYPE:Recorder 1\n\
+            B1101355206343N00006198WA0058700558\n\
+            B1101455206400S00006300EA0059000560\n";
+        let flight = read(Cursor::new(igc)).unwrap();
+        assert_eq!(flight.header.pilot, Some("John Doe".to_string()));
+        assert_eq!(flight.header.glider, Some("Ventus 2".to_string()));
+        assert_eq!(flight.gpx.creator, Some("Recorder 1".to_string()));
+
+        let points = &flight.gpx.tracks[0].segments[0].points;
+        assert_eq!(points.len(), 2);
+        assert!((points[0].point().y() - (52. + 6.343 / 60.)).abs() < 1e-9);
+        assert!((points[0].point().x() + 6.198 / 60.).abs() < 1e-9);
+        assert!(points[1].point().y() < 0.);
+        assert!(points[1].point().x() > 0.);
+        assert_eq!(points[0].elevation, Some(558.));
+        assert_eq!(flight.pressure_altitude, vec![Some(587.), Some(590.)]);
+
+        let midnight = Date::from_calendar_date(2022, Month::August, 12).unwrap().midnight().assume_utc().unix_timestamp();
+        assert_eq!(timestamp(&points[0]), midnight + 11 * 3600 + 60 + 35);
+    }
+
+    #[test]
+    fn old_date_header_without_colon() {
+        assert_eq!(header_value("HFDTE120822"), "120822");
+        assert_eq!(parse_date("120822"), Date::from_calendar_date(2022, Month::August, 12).ok());
+        assert_eq!(parse_date("320822"), None);
+    }
+
+    #[test]
+    fn fixes_after_midnight_are_on_the_next_day() {
+        let igc = "AXXX001\n\
+            HFDTE311222\n\
+            B2359505206343N00006198WA0058700558\n\
+            B0000105206343N00006198WA0058700558\n";
+        let flight = read(Cursor::new(igc)).unwrap();
+        let points = &flight.gpx.tracks[0].segments[0].points;
+        assert_eq!(timestamp(&points[1]) - timestamp(&points[0]), 20);
+        assert_eq!(OffsetDateTime::from(points[1].time.unwrap()).date(), Date::from_calendar_date(2023, Month::January, 1).unwrap());
+    }
+
+    #[test]
+    fn gps_altitude_of_v_fixes_is_dropped_if_zero() {
+        let igc = "AXXX001\n\
+            HFDTE120822\n\
+            B1101355206343N00006198WV0000000000\n\
+            B1101455206343N00006198WA0000000000\n\
+            B1101555206343N00006198WV0000000120\n";
+        let flight = read(Cursor::new(igc)).unwrap();
+        let points = &flight.gpx.tracks[0].segments[0].points;
+        assert_eq!(points[0].elevation, None);
+        assert_eq!(points[1].elevation, Some(0.));
+        assert_eq!(points[2].elevation, Some(120.));
+        // recorders without pressure sensor write 0 for every fix
+        assert_eq!(flight.pressure_altitude, vec![None, None, None]);
+    }
+
+    #[test]
+    fn file_without_fixes_has_no_track_points() {
+        let igc = "AXXX001\nHFDTE120822\n";
+        assert!(matches!(read(Cursor::new(igc)), Err(MaplineError::NoTrackPoints)));
+    }
+}
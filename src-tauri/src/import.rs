@@ -1,148 +1,370 @@
-use geo_types::Point;
-use gpx::{Gpx, TrackSegment, Track, read, Waypoint};
-use gpx::Time;
-use ulid::Ulid;
-use time::{OffsetDateTime};
-use chrono::prelude::{DateTime, Utc};
-use fitparser::{profile};
-
-use std::path::PathBuf;
-use std::fs::File;
-use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+use geojson::GeoJson;
+use gpx::{Gpx, Waypoint};
 
 use crate::line::arrange_display;
 use crate::track_analysis::{TrackAnalysis, Activity};
 use crate::errors::MaplineError;
-use crate::util;
-use crate::io::{write_geojson, write_gpx, write_track_analysis};
+use crate::{duplicate, elevation, flight, importer, index, paths, route, segment, util};
+use crate::importer::Importer;
+use crate::route::{AnalyzedRoute, Planned, Route};
+use crate::io::{begin_write, end_write, read_multisport_event, read_source, read_track_analysis, write_elevation, write_geojson, write_gpx, write_laps, write_multisport_event, write_sensor_data, write_source, write_track_analysis};
+use crate::multisport::{LegInfo, MultisportEvent};
+use crate::lap::Lap;
+use crate::sensor::SensorData;
+use crate::summary::DeviceSummary;
+use crate::flight::FlightHeader;
+use crate::source::{self, Source, SourceFile};
 
-pub fn gpx(gpx_path: &PathBuf) -> Result<TrackAnalysis, MaplineError> {
-    let file = File::open(gpx_path).unwrap();
-    let reader = BufReader::new(BufReader::new(file));
-    let gpx = read(reader).unwrap(); // TODO: remove unwrap
-    let geojson = arrange_display(&gpx, None, None);
-    
-    // TODO: implement
-    optimize_gpx(&gpx);
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Tracks of one file, the written ones and the skipped ones with the reason
+#[derive(Default)]
+pub struct FileImport {
+    pub written: Vec<TrackAnalysis>,
+    pub skipped: Vec<(usize, MaplineError)>, // index of the track in the file
+}
+
+/// Track read by one of the importers, not yet analyzed and written
+pub struct ParsedTrack {
+    pub gpx: Gpx,
+    pub activity: Option<Activity>,
+    pub extras: Extras,
+    pub source: Option<Source>,
+}
 
-    // TODO: take care of files with multiple tracks or segments
-    // TODO: handle files with no timestamp
-    let start_time = gpx.tracks[0].segments[0].points[0].time.unwrap();
-    if util::track_with_start_time_exists(&start_time.format().unwrap()) {
-        return Err(MaplineError::TrackAlreadyImported); // TODO: change to dialog with overrule option
+/// Track with its analysis and elevation profile, ready to be written
+pub struct AnalyzedTrack {
+    pub analysis: TrackAnalysis,
+    pub geojson: GeoJson,
+    pub gpx: Gpx,
+    pub elevation: Vec<(f64, f64)>,
+    pub elevation_coords: Vec<(f64, f64)>,
+    pub extras: Extras,
+    pub source: Option<Source>,
+}
+
+/// Data of an import in addition to the gps points
+#[derive(Default)]
+pub struct Extras {
+    pub sensor_data: SensorData,
+    pub laps: Vec<Lap>,
+    pub device_summary: Option<DeviceSummary>,
+    pub leg: Option<LegInfo>,
+    pub flight: Option<FlightHeader>, // set for flight logs, the recorded altitude is used instead of the elevation model
+}
+
+/// Reads and parses a file with the importer matching its extension. Files with
+/// an unknown extension are detected by their content. Returns the tracks and
+/// the planned routes and waypoints of the file.
+pub fn read_path(path: &PathBuf) -> Result<(Vec<ParsedTrack>, Planned), MaplineError> {
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string();
+    let data = read_file(path)?;
+    let (importer, format) = importer_for(&extension(path), &data)?;
+    let (mut tracks, planned) = parse_with_planned(importer, &data)?;
+    attach_source(&mut tracks, data, &file_name, &format);
+    Ok((tracks, planned))
+}
+
+/// Importer of the extension, or detected by the content if the extension is
+/// missing or unknown. Returns the importer and the format stored with the source.
+fn importer_for(extension: &str, data: &[u8]) -> Result<(&'static dyn Importer, String), MaplineError> {
+    match importer::find(extension) {
+        Some(i) => Ok((i, extension.to_string())),
+        None => match importer::detect(data) {
+            Some(i) => Ok((i, i.info().id.to_string())),
+            None => Err(MaplineError::UnsupportedFormat),
+        },
     }
-    
-    // analyze geo data
-    let track_analysis = TrackAnalysis::new(None, &geojson, &gpx, None);
-    let geojson = arrange_display(&gpx, Some(geojson), Some(&track_analysis.pauses));
-    write_track_analysis(&track_analysis).unwrap();
-    write_geojson(&geojson, &track_analysis.ulid).unwrap();
-    write_gpx(&gpx, &track_analysis.ulid).unwrap();
-    
-    Ok(track_analysis)
 }
 
-pub fn fit(fit_path: &PathBuf) -> Result<TrackAnalysis, MaplineError> {
-    let mut fp = match File::open(fit_path) {
+/// Imports the (decompressed) content of a file, format is its extension
+pub fn data(data: Vec<u8>, file_name: &str, format: &str, force: bool) -> Result<FileImport, MaplineError> {
+    let (importer, format) = importer_for(format, &data)?;
+    let (mut tracks, planned) = parse_with_planned(importer, &data)?;
+    attach_source(&mut tracks, data, file_name, &format);
+    let (tracks, skipped) = select(tracks, force, None);
+    let mut analyzed: Vec<AnalyzedTrack> = vec![];
+    for (_, t) in tracks {
+        analyzed.push(analyze(t, None)?);
+    }
+    let routes = route::analyze_all(planned.routes)?;
+    let (written, _) = write_with_planned(analyzed, routes, planned.waypoints)?;
+    Ok(FileImport { written, skipped })
+}
+
+/// Fails if the file contains neither tracks nor routes or waypoints
+fn parse_with_planned(importer: &dyn Importer, data: &[u8]) -> Result<(Vec<ParsedTrack>, Planned), MaplineError> {
+    let (tracks, planned) = importer.parse_with_planned(data)?;
+    if tracks.is_empty() && planned.is_empty() {
+        return Err(MaplineError::NoTrackPoints);
+    }
+    Ok((tracks, planned))
+}
+
+/// Adds the original file to the parsed tracks, it is copied into every track folder
+pub fn attach_source(tracks: &mut Vec<ParsedTrack>, data: Vec<u8>, file_name: &str, format: &str) {
+    let checksum = source::checksum(&data);
+    let data = Arc::new(data);
+    for (i, t) in tracks.iter_mut().enumerate() {
+        t.source = Some(Source {
+            file: SourceFile { file_name: file_name.to_string(), format: format.to_string(), checksum: checksum.clone(), part: i },
+            data: data.clone(),
+        });
+    }
+}
+
+/// Rebuilds record.gpx and the analysis of a track from its original file.
+/// Changes made by the user (name, activity, ...) are kept.
+pub fn reimport(ulid: &String) -> Result<TrackAnalysis, MaplineError> {
+    let (file, data) = match read_source(ulid) {
         Err(err) => return Err(MaplineError::ImportError(err.to_string())),
-        Ok(f) => f,
+        Ok(s) => s,
     };
-    // import creator and add to gpx
-    let mut activity: Activity = Activity::Other;
-    let mut creator: String = "unknown".to_string();
-    let mut track_segment = TrackSegment::new();
-    let parsed_fit = match fitparser::from_reader(&mut fp) {
+    let old_ta = match read_track_analysis(ulid) {
         Err(err) => return Err(MaplineError::ImportError(err.to_string())),
-        Ok(pf) => pf,
+        Ok(ta) => ta,
     };
-    
-    // TODO: use field enhanced_speed
-    for data in parsed_fit {
-        if data.kind() == profile::MesgNum::Record {
-            let mut lat: Option<f64> = None;
-            let mut long: Option<f64> = None;
-            // let ele: i32;
-            let mut timestamp: Option<DateTime<Utc>> = None;
-            // println!("{:#?}", data);
-            for f in data.fields() {
-                match f.name() {
-                    "position_lat" => lat = Some(f.value().to_string().parse::<f64>().unwrap() * 0.000000083819032),
-                    "position_long" => long = Some(f.value().to_string().parse::<f64>().unwrap() * 0.000000083819032),
-                    "timestamp" => timestamp = Some(f.value().to_string().parse::<DateTime<Utc>>().unwrap()),
-                    // "enhanced_speed" => (),
-                    _ => (),
-                }
-            }
-            match (lat, long, timestamp) {
-                (Some(la), Some(lo), Some(ti)) => {
-                    let mut point = Waypoint::new(Point::new(lo, la));
-                    point.time = Some(Time::from(OffsetDateTime::from_unix_timestamp(ti.timestamp()).unwrap()));
-                    track_segment.points.push(point);
-                }
-                _ => (),
+    let mut tracks = parse(&data, &file.format)?;
+    if file.part >= tracks.len() {
+        return Err(MaplineError::ImportError("part of source file not found".to_string()));
+    }
+    let mut track = tracks.swap_remove(file.part);
+    track.activity = Some(old_ta._type.clone());
+    let mut ta = write(analyze(track, Some(ulid.clone()))?)?;
+    ta.name = old_ta.name;
+    ta.summary_source = old_ta.summary_source;
+    ta.multisport = old_ta.multisport;
+    ta.planned_route = old_ta.planned_route;
+    ta.tags = old_ta.tags;
+    ta.archived = old_ta.archived;
+    ta.trashed = old_ta.trashed;
+    write_track_analysis(&ta).map_err(write_error)?;
+    // distance and time of the leg may have changed with the new parse
+    if let Some(id) = &ta.multisport {
+        match read_multisport_event(id) {
+            Ok(mut event) => {
+                event.update_leg(&ta);
+                write_multisport_event(&event).map_err(write_error)?;
             }
+            Err(e) => println!("Multisport event {} of {} not updated: {:?}", id, ulid, e),
         }
-        
-        else if data.kind() == profile::MesgNum::FileId {
-            println!("{:?}", data);
-            for f in data.fields() {
-                match f.name() {
-                    "type" => {
-                        if f.value().to_string() != "activity" {
-                            return Err(MaplineError::FitFileNotAnActivity);
-                        }
-                    }
-                    "manufacturer" => creator = f.value().to_string(),
-                    _ => break,
-                }
-            }
+    }
+    // the elevation source chosen by the user is kept
+    match old_ta.elevation_source {
+        Some(s) if ta.elevation_source != Some(s) => util::set_elevation_source(ulid, s),
+        _ => Ok(ta),
+    }
+}
+
+/// Reads a file and decompresses it if it is gzip compressed
+pub fn read_file(path: &PathBuf) -> Result<Vec<u8>, MaplineError> {
+    match std::fs::read(path) {
+        Err(err) => Err(MaplineError::FileError(err.to_string())),
+        Ok(d) => decompress(d),
+    }
+}
+
+/// Decompresses gzip data (detected by its magic bytes), other data is returned unchanged
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>, MaplineError> {
+    if !data.starts_with(&GZIP_MAGIC) {
+        return Ok(data);
+    }
+    let mut decompressed: Vec<u8> = vec![];
+    match GzDecoder::new(&data[..]).read_to_end(&mut decompressed) {
+        Err(err) => Err(MaplineError::ParseError(err.to_string())),
+        Ok(_) => Ok(decompressed),
+    }
+}
+
+/// Lowercase extension of a file, a trailing .gz is skipped (e.g. "fit" for ride.fit.gz)
+pub fn extension(path: &Path) -> String {
+    let name = path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let name = name.strip_suffix(".gz").unwrap_or(name.as_str());
+    match name.rsplit_once('.') {
+        Some((_, e)) => e.to_string(),
+        None => String::new(),
+    }
+}
+
+/// Parses the content of a file with the importer matching the extension
+pub fn parse(data: &[u8], extension: &str) -> Result<Vec<ParsedTrack>, MaplineError> {
+    match importer::find(extension) {
+        Some(i) => i.parse(data),
+        None => Err(MaplineError::UnsupportedFormat),
+    }
+}
+
+/// Checks every track of a file, tracks that fail the check are skipped so the
+/// other tracks of the file are still imported. With parts, only the tracks at
+/// these indices are kept (e.g. the duplicates of a file imported anyway).
+pub fn select(tracks: Vec<ParsedTrack>, force: bool, parts: Option<&[usize]>) -> (Vec<(usize, ParsedTrack)>, Vec<(usize, MaplineError)>) {
+    let mut selected: Vec<(usize, ParsedTrack)> = vec![];
+    let mut skipped: Vec<(usize, MaplineError)> = vec![];
+    for (i, t) in tracks.into_iter().enumerate() {
+        if parts.map(|p| !p.contains(&i)).unwrap_or(false) {
+            continue;
         }
-        
-        else if data.kind() == profile::MesgNum::Sport {
-            // TODO: extend for more activities
-            for f in data.fields() {
-                match f.name() {
-                    "sport" => {
-                        match f.value().to_string().as_str() {
-                            "xcskiing" => activity = Activity::XCSkiing, // has to be tested with xc capable device
-                            "cycling" => activity = Activity::Cycling,
-                            "running" => activity = Activity::Running,
-                            "hiking" => activity = Activity::Hiking,
-                            "walking" => activity = Activity::Hiking, // same activity as hiking
-                            "swimming" => activity = Activity::Swimming, // has to be tested with swimming capable device
-                            "inline_skating" => activity = Activity::InlineSkating, // has to be tested with is capable device
-                            _ => activity = Activity::Other,
-                        }
-                    }
-                    _ => (),
-                }
-            }
+        match check(&t, force) {
+            Ok(()) => selected.push((i, t)),
+            Err(e) => skipped.push((i, e)),
         }
+    }
+    (selected, skipped)
+}
 
+/// Fails if the track has no points or, unless forced, is already in the library
+pub fn check(track: &ParsedTrack, force: bool) -> Result<(), MaplineError> {
+    if segment::points(&track.gpx).is_empty() {
+        return Err(MaplineError::NoTrackPoints);
+    }
+    if !force {
+        if let Some(candidate) = duplicate::find(&track.gpx) {
+            return Err(MaplineError::TrackAlreadyImported(candidate));
+        }
     }
-    let mut track = Track::new();
-    track.segments.push(track_segment);
-    let mut gpx = Gpx::default();
-    gpx.tracks.push(track);
-    gpx.version = gpx::GpxVersion::Gpx11;
-    gpx.creator = Some(creator);
+    Ok(())
+}
 
-    let start_time = gpx.tracks[0].segments[0].points[0].time.unwrap();
-    let end_time = gpx.tracks[0].segments[0].points.last().unwrap().time.unwrap();
-    // TODO: check this earlier
-    if util::track_with_start_time_exists(&start_time.format().unwrap()) {
-        return Err(MaplineError::TrackAlreadyImported); // TODO: change to dialog with overrule option
+/// Analyzes the gpx (including the elevation lookup) without writing anything
+pub fn analyze(track: ParsedTrack, ulid: Option<String>) -> Result<AnalyzedTrack, MaplineError> {
+    let ParsedTrack { gpx, activity, extras, source } = track;
+    if segment::points(&gpx).is_empty() {
+        return Err(MaplineError::NoTrackPoints);
     }
     let geojson = arrange_display(&gpx, None, None);
 
-    let track_analysis = TrackAnalysis::new(None, &geojson, &gpx, Some(activity));
-    write_track_analysis(&track_analysis).unwrap();
-    let geojson = arrange_display(&gpx, Some(geojson), Some(&track_analysis.pauses));
-    write_geojson(&geojson, &track_analysis.ulid).unwrap();
-    write_gpx(&gpx, &track_analysis.ulid).unwrap();
-    Ok(track_analysis)
+    // analyze geo data
+    let source = elevation::default_source(&gpx, &extras.sensor_data, extras.flight.is_some());
+    let (mut analysis, elevation, elevation_coords) = TrackAnalysis::analyze(ulid, &geojson, &gpx, activity, source, &extras.sensor_data);
+    analysis.device_summary = extras.device_summary.clone();
+    analysis.flight = extras.flight.as_ref().map(|h| flight::summary(&gpx, &extras.sensor_data, h));
+    let geojson = arrange_display(&gpx, Some(geojson), Some(&analysis.pauses));
+    Ok(AnalyzedTrack { analysis, geojson, gpx, elevation, elevation_coords, extras, source })
+}
+
+/// Writes all files of the track
+fn write(track: AnalyzedTrack) -> Result<TrackAnalysis, MaplineError> {
+    let AnalyzedTrack { analysis, geojson, gpx, elevation, elevation_coords, extras, source } = track;
+    begin_write(&analysis.ulid).map_err(write_error)?;
+    // the original is written first, repair imports an interrupted first import
+    // again from it
+    if let Some(s) = source {
+        write_source(&s.file, &s.data, &analysis.ulid).map_err(write_error)?;
+    }
+    write_track_analysis(&analysis).map_err(write_error)?;
+    write_elevation(elevation, elevation_coords, &analysis.ulid).map_err(write_error)?;
+    write_geojson(&geojson, &analysis.ulid).map_err(write_error)?;
+    write_gpx(&gpx, &analysis.ulid).map_err(write_error)?;
+    // files of a previous import (see reimport) are removed if the file has no such data
+    if extras.sensor_data.has_values() {
+        write_sensor_data(&extras.sensor_data, &analysis.ulid).map_err(write_error)?;
+    } else {
+        remove_if_exists(&paths::track_sensors(&analysis.ulid)).map_err(write_error)?;
+    }
+    if !extras.laps.is_empty() {
+        write_laps(&extras.laps, &analysis.ulid).map_err(write_error)?;
+    } else {
+        remove_if_exists(&paths::track_laps(&analysis.ulid)).map_err(write_error)?;
+    }
+    end_write(&analysis.ulid).map_err(write_error)?;
+    Ok(analysis)
+}
+
+/// Writes the analyzed tracks of one file. Legs of a multisport file are linked
+/// to one event. If writing fails, the folders of all tracks are removed again,
+/// so no half written tracks are left in the library.
+pub fn write_all(mut tracks: Vec<AnalyzedTrack>) -> Result<Vec<TrackAnalysis>, MaplineError> {
+    let legs: Vec<(TrackAnalysis, LegInfo)> = tracks.iter()
+        .filter_map(|t| t.extras.leg.clone().map(|l| (t.analysis.clone(), l)))
+        .collect();
+    let event = if legs.is_empty() { None } else { Some(MultisportEvent::new(&legs)) };
+    if let Some(e) = &event {
+        for t in tracks.iter_mut().filter(|t| t.extras.leg.is_some()) {
+            t.analysis.multisport = Some(e.id.clone());
+        }
+    }
+
+    let ulids: Vec<String> = tracks.iter().map(|t| t.analysis.ulid.clone()).collect();
+    let mut result: Vec<TrackAnalysis> = vec![];
+    for t in tracks {
+        match write(t) {
+            Ok(ta) => result.push(ta),
+            Err(e) => {
+                remove_tracks(&ulids);
+                return Err(e);
+            }
+        }
+    }
+    if let Some(e) = event {
+        if let Err(err) = write_multisport_event(&e) {
+            remove_tracks(&ulids);
+            return Err(write_error(err));
+        }
+    }
+    Ok(result)
+}
+
+/// Writes the tracks of a file together with its routes and waypoints. The routes
+/// are written first to link the tracks to them, and removed again if the tracks
+/// could not be written. Returns the tracks and the routes new in the library.
+pub fn write_with_planned(mut tracks: Vec<AnalyzedTrack>, routes: Vec<AnalyzedRoute>, waypoints: Vec<Waypoint>) -> Result<(Vec<TrackAnalysis>, Vec<Route>), MaplineError> {
+    let routes = route::write_all(routes, &mut tracks)?;
+    let analysis = match write_all(tracks) {
+        Ok(a) => a,
+        Err(e) => {
+            route::remove_all(&routes);
+            return Err(e);
+        }
+    };
+    // the tracks are already in the library, a failure only loses the waypoints
+    if let Err(e) = route::add_pois(waypoints) {
+        println!("Could not add points of interest: {:?}", e);
+    }
+    Ok((analysis, routes))
+}
+
+/// Checks, analyzes and writes the tracks of one file, all of them or the parts
+pub fn store_all(tracks: Vec<ParsedTrack>, force: bool, parts: Option<&[usize]>) -> Result<FileImport, MaplineError> {
+    let (tracks, skipped) = select(tracks, force, parts);
+    let mut analyzed: Vec<AnalyzedTrack> = vec![];
+    for (_, t) in tracks {
+        analyzed.push(analyze(t, None)?);
+    }
+    Ok(FileImport { written: write_all(analyzed)?, skipped })
+}
+
+/// Removes the folders and index rows of the tracks written before a failure
+fn remove_tracks(ulids: &Vec<String>) {
+    for ulid in ulids {
+        if let Err(e) = std::fs::remove_dir_all(paths::track(ulid)) {
+            println!("Could not remove track {}: {:?}", ulid, e);
+        }
+        if let Err(e) = index::remove(ulid) {
+            println!("Could not remove track {} from the index: {:?}", ulid, e);
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), std::io::Error> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn write_error(err: std::io::Error) -> MaplineError {
+    MaplineError::WriteError(err.to_string())
 }
 
-fn optimize_gpx(_gpx: &Gpx) {
-    // todo!("reduce number of track points");
+/// Tracks without activity or extras, the activity is taken from the gpx track type
+pub fn untyped(gpxs: Vec<Gpx>) -> Vec<ParsedTrack> {
+    gpxs.into_iter().map(|gpx| ParsedTrack { gpx, activity: None, extras: Extras::default(), source: None }).collect()
 }
@@ -0,0 +1,106 @@
+use std::io::Cursor;
+
+use gpx::{read, Gpx};
+
+use crate::errors::MaplineError;
+use crate::fit::FitImporter;
+use crate::csv_track::CsvImporter;
+use crate::geojson_track::GeoJsonImporter;
+use crate::igc::IgcImporter;
+use crate::import::{self, ParsedTrack};
+use crate::kml::KmlImporter;
+use crate::route::Planned;
+use crate::tcx::TcxImporter;
+
+/// Number of bytes searched for the root element of xml formats
+const XML_HEAD_LEN: usize = 1024;
+
+/// Metadata of a format, used for the menu and the file dialogs
+#[derive(Clone, Debug)]
+pub struct FormatInfo {
+    pub id: &'static str, // also used as menu id and stored as format of the original file
+    pub name: &'static str,
+    pub extensions: &'static [&'static str], // lowercase, without .gz
+    pub column_mapping: bool, // the user maps the columns before the import (csv)
+}
+
+/// Reads one file format into the normalized track model. Adding a format means
+/// implementing this trait and adding the importer to the registry below.
+pub trait Importer: Sync {
+    fn info(&self) -> FormatInfo;
+
+    /// True if the (decompressed) content is of this format. Used for files
+    /// with a missing or unknown extension.
+    fn detect(&self, data: &[u8]) -> bool;
+
+    fn parse(&self, data: &[u8]) -> Result<Vec<ParsedTrack>, MaplineError>;
+
+    /// Tracks and the planned routes and waypoints of the file. Formats without
+    /// routes only implement parse.
+    fn parse_with_planned(&self, data: &[u8]) -> Result<(Vec<ParsedTrack>, Planned), MaplineError> {
+        Ok((self.parse(data)?, Planned::default()))
+    }
+}
+
+/// All importers, in the order of the menu
+pub fn registry() -> Vec<&'static dyn Importer> {
+    vec![&GpxImporter, &FitImporter, &TcxImporter, &KmlImporter, &IgcImporter, &GeoJsonImporter, &CsvImporter]
+}
+
+/// Importer with the id or extension of format (e.g. "kmz")
+pub fn find(format: &str) -> Option<&'static dyn Importer> {
+    let format = format.to_lowercase();
+    registry().into_iter().find(|i| {
+        let info = i.info();
+        info.id == format || info.extensions.contains(&format.as_str())
+    })
+}
+
+/// Importer detecting the content by its magic bytes
+pub fn detect(data: &[u8]) -> Option<&'static dyn Importer> {
+    registry().into_iter().find(|i| i.detect(data))
+}
+
+/// True if the start of data contains the xml element (e.g. "<gpx")
+pub fn xml_root(data: &[u8], element: &str) -> bool {
+    let head = &data[..data.len().min(XML_HEAD_LEN)];
+    String::from_utf8_lossy(head).contains(element)
+}
+
+pub struct GpxImporter;
+
+impl Importer for GpxImporter {
+    fn info(&self) -> FormatInfo {
+        FormatInfo { id: "gpx", name: "GPX", extensions: &["gpx"], column_mapping: false }
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        xml_root(data, "<gpx")
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<Vec<ParsedTrack>, MaplineError> {
+        Ok(self.parse_with_planned(data)?.0)
+    }
+
+    /// Routes and waypoints are removed from the gpx of the track, files with
+    /// only routes or waypoints have no tracks
+    fn parse_with_planned(&self, data: &[u8]) -> Result<(Vec<ParsedTrack>, Planned), MaplineError> {
+        let mut gpx = match read(Cursor::new(data)) {
+            Err(err) => return Err(MaplineError::ParseError(err.to_string())),
+            Ok(g) => g,
+        };
+        let planned = Planned::from_gpx(&gpx);
+        gpx.routes.clear();
+        gpx.waypoints.clear();
+        if gpx.tracks.is_empty() && !planned.is_empty() {
+            return Ok((vec![], planned));
+        }
+        // TODO: implement
+        optimize_gpx(&gpx);
+        Ok((import::untyped(vec![gpx]), planned))
+    }
+}
+
+fn optimize_gpx(_gpx: &Gpx) {
+    // todo!("reduce number of track points");
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use rusqlite::{params, params_from_iter, Connection};
+use rusqlite::types::Value;
+use serde::Deserialize;
+
+use crate::{duplicate, io, paths};
+use crate::errors::MaplineError;
+use crate::track_analysis::TrackAnalysis;
+use crate::track_note::TrackNote;
+
+/// Time to wait for the lock of another import worker
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stored as user_version of the database, an index of an older version is
+/// dropped and read from the track folders again
+const INDEX_VERSION: i32 = 2;
+
+/// The track folders are the source of truth, the index only holds copies of
+/// the analyses and the searchable fields. The full analysis is stored as json,
+/// so listing the library does not read the folders.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS tracks (
+    ulid TEXT PRIMARY KEY,
+    start_ts INTEGER,
+    end_ts INTEGER,
+    activity TEXT NOT NULL,
+    name TEXT,
+    x_min REAL NOT NULL,
+    x_max REAL NOT NULL,
+    y_min REAL NOT NULL,
+    y_max REAL NOT NULL,
+    archived INTEGER NOT NULL,
+    trashed INTEGER,
+    modified INTEGER NOT NULL,
+    analysis TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS tracks_start ON tracks (start_ts);
+CREATE TABLE IF NOT EXISTS tags (
+    ulid TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (ulid, tag)
+);
+CREATE TABLE IF NOT EXISTS notes (
+    id TEXT PRIMARY KEY,
+    ulid TEXT NOT NULL,
+    comment TEXT,
+    lon REAL NOT NULL,
+    lat REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS notes_ulid ON notes (ulid);
+";
+
+/// Filter of the track list, all set fields must match. Trashed tracks never
+/// match, archived tracks only if archived is set or the text is searched.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct TrackFilter {
+    pub archived: Option<bool>,
+    pub activity: Option<String>,
+    pub tag: Option<String>,
+    pub text: Option<String>, // part of the name, a tag or a note
+    pub from: Option<i64>, // unix timestamps of the start time
+    pub to: Option<i64>,
+    pub bbox: Option<(f64, f64, f64, f64)>, // (x_min, x_max, y_min, y_max), tracks overlapping it
+}
+
+fn open() -> Result<Connection, MaplineError> {
+    let conn = Connection::open(paths::index()).map_err(db_error)?;
+    conn.busy_timeout(BUSY_TIMEOUT).map_err(db_error)?;
+    let version: i32 = conn.query_row("PRAGMA user_version", [], |r| r.get(0)).map_err(db_error)?;
+    if version < INDEX_VERSION {
+        conn.execute_batch("DROP TABLE IF EXISTS tracks; DROP TABLE IF EXISTS tags; DROP TABLE IF EXISTS notes;").map_err(db_error)?;
+        conn.pragma_update(None, "user_version", INDEX_VERSION).map_err(db_error)?;
+    }
+    conn.execute_batch(SCHEMA).map_err(db_error)?;
+    Ok(conn)
+}
+
+fn db_error(err: rusqlite::Error) -> MaplineError {
+    MaplineError::IndexError(err.to_string())
+}
+
+/// Adds or replaces the track, called whenever its analysis is written
+pub fn update(ta: &TrackAnalysis) -> Result<(), MaplineError> {
+    let conn = open()?;
+    insert(&conn, ta, modified(&paths::track_analysis(&ta.ulid)))
+}
+
+/// Replaces the notes of the track
+pub fn update_notes(ulid: &str, notes: &[TrackNote]) -> Result<(), MaplineError> {
+    let conn = open()?;
+    insert_notes(&conn, ulid, notes)
+}
+
+pub fn remove(ulid: &str) -> Result<(), MaplineError> {
+    let conn = open()?;
+    delete(&conn, ulid)
+}
+
+fn insert(conn: &Connection, ta: &TrackAnalysis, modified: i64) -> Result<(), MaplineError> {
+    let analysis = serde_json::to_string(ta).map_err(|e| MaplineError::IndexError(e.to_string()))?;
+    let time_range = duplicate::analysis_time_range(ta);
+    conn.execute(
+        "INSERT OR REPLACE INTO tracks (ulid, start_ts, end_ts, activity, name, x_min, x_max, y_min, y_max, archived, trashed, modified, analysis)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![ta.ulid, time_range.map(|t| t.0), time_range.map(|t| t.1), format!("{:?}", ta._type), ta.name,
+            ta.x_min.0, ta.x_max.0, ta.y_min.1, ta.y_max.1, ta.archived, ta.trashed, modified, analysis],
+    ).map_err(db_error)?;
+    conn.execute("DELETE FROM tags WHERE ulid = ?1", params![ta.ulid]).map_err(db_error)?;
+    for tag in &ta.tags {
+        conn.execute("INSERT OR IGNORE INTO tags (ulid, tag) VALUES (?1, ?2)", params![ta.ulid, tag]).map_err(db_error)?;
+    }
+    Ok(())
+}
+
+fn insert_notes(conn: &Connection, ulid: &str, notes: &[TrackNote]) -> Result<(), MaplineError> {
+    conn.execute("DELETE FROM notes WHERE ulid = ?1", params![ulid]).map_err(db_error)?;
+    for n in notes {
+        conn.execute(
+            "INSERT OR REPLACE INTO notes (id, ulid, comment, lon, lat) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![n.id, ulid, n.comment, n.coords.0, n.coords.1],
+        ).map_err(db_error)?;
+    }
+    Ok(())
+}
+
+fn delete(conn: &Connection, ulid: &str) -> Result<(), MaplineError> {
+    for table in ["tracks", "tags", "notes"] {
+        conn.execute(&format!("DELETE FROM {} WHERE ulid = ?1", table), params![ulid]).map_err(db_error)?;
+    }
+    Ok(())
+}
+
+/// Modification time of the file in milliseconds, 0 if it does not exist
+fn modified(path: &Path) -> i64 {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Brings the index in line with the track folders: tracks whose analysis
+/// changed on disk (e.g. edited by hand or written by an older version) are
+/// read again, tracks without folder are removed. Returns the number of
+/// tracks read from disk.
+pub fn sync() -> Result<usize, MaplineError> {
+    let mut conn = open()?;
+    // rows of an older analysis version can not be read by this version
+    let indexed: HashMap<String, i64> = {
+        let mut stmt = conn.prepare("SELECT ulid, modified, json_extract(analysis, '$.version') FROM tracks").map_err(db_error)?;
+        let rows = stmt.query_map([], |r| {
+            let version: Option<i64> = r.get(2)?;
+            let modified: i64 = if version == Some(crate::ANALYSIS_VERSION as i64) { r.get(1)? } else { -1 };
+            Ok((r.get::<_, String>(0)?, modified))
+        }).map_err(db_error)?;
+        rows.filter_map(|r| r.ok()).collect()
+    };
+    let on_disk = track_ulids();
+
+    let tx = conn.transaction().map_err(db_error)?;
+    let mut count = 0;
+    for ulid in &on_disk {
+        let path = paths::track_analysis(ulid);
+        if indexed.get(ulid) == Some(&modified(&path)) {
+            continue;
+        }
+        // reading upgrades old analyses, so the time is taken afterwards
+        match io::read_track_analysis(ulid) {
+            Ok(ta) => {
+                insert(&tx, &ta, modified(&path))?;
+                insert_notes(&tx, ulid, &io::read_track_notes(ulid).unwrap_or_default())?;
+                count += 1;
+            }
+            Err(e) => println!("Could not index track {}: {:?}", ulid, e),
+        }
+    }
+    for ulid in indexed.keys().filter(|u| !on_disk.contains(u)) {
+        delete(&tx, ulid)?;
+    }
+    tx.commit().map_err(db_error)?;
+    Ok(count)
+}
+
+/// Drops the index and reads all tracks from disk again
+pub fn rebuild() -> Result<usize, MaplineError> {
+    let conn = open()?;
+    conn.execute_batch("DELETE FROM tracks; DELETE FROM tags; DELETE FROM notes;").map_err(db_error)?;
+    sync()
+}
+
+/// Ulids of all track folders
+pub fn track_ulids() -> Vec<String> {
+    match std::fs::read_dir(paths::tracks()) {
+        Err(_) => vec![],
+        Ok(dirs) => dirs.filter_map(|d| d.ok())
+            .filter(|d| d.path().is_dir())
+            .map(|d| d.file_name().to_string_lossy().to_string())
+            .collect(),
+    }
+}
+
+/// Reads the analysis of every track folder, used if the index can not be opened
+pub fn read_from_disk() -> Vec<TrackAnalysis> {
+    track_ulids().iter()
+        .filter_map(|u| match io::read_track_analysis(u) {
+            Ok(ta) => Some(ta),
+            Err(_) => { println!("Could not load track {}.", u); None },
+        })
+        .collect()
+}
+
+/// Tracks that are neither archived nor trashed
+pub fn list() -> Result<Vec<TrackAnalysis>, MaplineError> {
+    filter(&TrackFilter::default())
+}
+
+/// All tracks, including the archived and trashed ones
+pub fn all() -> Result<Vec<TrackAnalysis>, MaplineError> {
+    query("SELECT analysis FROM tracks t ORDER BY t.start_ts DESC", vec![])
+}
+
+/// Tracks in the trash, latest deletion first
+pub fn trash() -> Result<Vec<TrackAnalysis>, MaplineError> {
+    query("SELECT analysis FROM tracks t WHERE t.trashed IS NOT NULL ORDER BY t.trashed DESC", vec![])
+}
+
+/// Tracks matching the filter, latest first
+pub fn filter(filter: &TrackFilter) -> Result<Vec<TrackAnalysis>, MaplineError> {
+    let mut sql = "SELECT analysis FROM tracks t WHERE t.trashed IS NULL".to_string();
+    let mut values: Vec<Value> = vec![];
+    match filter.archived {
+        Some(archived) => {
+            sql += " AND t.archived = ?";
+            values.push(Value::Integer(archived as i64));
+        }
+        None if filter.text.is_none() => sql += " AND t.archived = 0",
+        None => {}
+    }
+    if let Some(activity) = &filter.activity {
+        sql += " AND lower(t.activity) = lower(?)";
+        values.push(Value::Text(activity.clone()));
+    }
+    if let Some(tag) = &filter.tag {
+        sql += " AND EXISTS (SELECT 1 FROM tags g WHERE g.ulid = t.ulid AND g.tag = ?)";
+        values.push(Value::Text(tag.clone()));
+    }
+    if let Some(text) = &filter.text {
+        sql += " AND (t.name LIKE ?
+            OR EXISTS (SELECT 1 FROM tags g WHERE g.ulid = t.ulid AND g.tag LIKE ?)
+            OR EXISTS (SELECT 1 FROM notes n WHERE n.ulid = t.ulid AND n.comment LIKE ?))";
+        let pattern = format!("%{}%", text);
+        values.extend(std::iter::repeat(Value::Text(pattern)).take(3));
+    }
+    if let Some(from) = filter.from {
+        sql += " AND t.start_ts >= ?";
+        values.push(Value::Integer(from));
+    }
+    if let Some(to) = filter.to {
+        sql += " AND t.start_ts <= ?";
+        values.push(Value::Integer(to));
+    }
+    if let Some(bbox) = filter.bbox {
+        sql += " AND t.x_min <= ? AND t.x_max >= ? AND t.y_min <= ? AND t.y_max >= ?";
+        values.extend([bbox.1, bbox.0, bbox.3, bbox.2].iter().map(|v| Value::Real(*v)));
+    }
+    sql += " ORDER BY t.start_ts DESC";
+    query(&sql, values)
+}
+
+/// Tracks overlapping the bounding box and, for tracks with time, the time
+/// range. Tracks without time are only compared with tracks without time.
+/// Archived tracks are still in the library, trashed tracks are not.
+pub fn duplicate_candidates(bbox: (f64, f64, f64, f64), time_range: Option<(i64, i64)>) -> Result<Vec<TrackAnalysis>, MaplineError> {
+    let mut sql = "SELECT analysis FROM tracks t WHERE t.trashed IS NULL AND t.x_min <= ? AND t.x_max >= ? AND t.y_min <= ? AND t.y_max >= ?".to_string();
+    let mut values: Vec<Value> = [bbox.1, bbox.0, bbox.3, bbox.2].iter().map(|v| Value::Real(*v)).collect();
+    match time_range {
+        Some((start, end)) => {
+            sql += " AND t.start_ts <= ? AND t.end_ts >= ?";
+            values.push(Value::Integer(end));
+            values.push(Value::Integer(start));
+        }
+        None => sql += " AND t.start_ts IS NULL",
+    }
+    query(&sql, values)
+}
+
+/// All tags in use outside of the trash, sorted
+pub fn tags() -> Result<Vec<String>, MaplineError> {
+    let conn = open()?;
+    let mut stmt = conn.prepare("SELECT DISTINCT g.tag FROM tags g JOIN tracks t ON t.ulid = g.ulid WHERE t.trashed IS NULL ORDER BY g.tag").map_err(db_error)?;
+    let rows = stmt.query_map([], |r| r.get::<_, String>(0)).map_err(db_error)?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+fn query(sql: &str, values: Vec<Value>) -> Result<Vec<TrackAnalysis>, MaplineError> {
+    let conn = open()?;
+    let mut stmt = conn.prepare(sql).map_err(db_error)?;
+    let rows = stmt.query_map(params_from_iter(values.iter()), |r| r.get::<_, String>(0)).map_err(db_error)?;
+    let result = rows.filter_map(|r| r.ok())
+        .filter_map(|a| serde_json::from_str::<TrackAnalysis>(&a).ok())
+        .collect();
+    Ok(result)
+}
@@ -1,13 +1,19 @@
-use std::{path::PathBuf, fs::{self, File}};
-use std::io::{self, BufReader, BufWriter};
+use std::{path::{Path, PathBuf}, fs::{self, File}};
+use std::io::{self, BufReader, Write};
 use std::io::Cursor;
 
 use geojson::GeoJson;
 use gpx::{Gpx, read};
 use tokio;
 use serde_json;
+use ulid::Ulid;
 
-use crate::{paths, track_analysis::TrackAnalysis, track_note::TrackNote};
+use crate::errors::MaplineError;
+use crate::track_note::TrackNotes;
+use crate::{index, migrate, paths, lap::Lap, multisport::MultisportEvent, route::{Poi, Route}, sensor::SensorData, source::SourceFile, track_analysis::TrackAnalysis, track_note::TrackNote};
+
+/// Suffix of temp files, followed by a ulid
+const TEMP_SUFFIX: &str = ".tmp-";
 
 pub fn read_geojson(ulid: &String) -> Option<GeoJson> {
     let path = paths::track_geojson(ulid);
@@ -26,20 +32,26 @@ pub fn read_geojson(ulid: &String) -> Option<GeoJson> {
 pub fn read_gpx(ulid: &String) -> Option<Gpx> {
     let path = paths::track_gpx(ulid);
 
-    let file = File::open(path).unwrap();
+    let file = File::open(path).ok()?;
     let reader = BufReader::new(file);
-    let gpx = read(reader); // TODO: remove unwrap
+    let gpx = read(reader);
     match gpx {
         Ok(g) => Some(g),
         _ => None
     }
 }
 
+/// Analyses of older versions are upgraded in memory, the file is left as it is
+/// (see migrate::upgrade_track_analysis)
 pub fn read_track_analysis(ulid: &String) -> Result<TrackAnalysis, io::Error> {
-    let path = paths::track_analysis(ulid);
-    let json_string = fs::read_to_string(path)?;
-    let ta: TrackAnalysis = serde_json::from_str(&json_string.as_str())?;
-    Ok(ta)
+    migrate::track_analysis(ulid).map_err(migrate_error)
+}
+
+fn migrate_error(err: MaplineError) -> io::Error {
+    match err {
+        MaplineError::FileError(e) => io::Error::new(io::ErrorKind::NotFound, e),
+        e => io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)),
+    }
 }
 
 pub fn read_elevation(ulid: &str) -> Result<(Vec<(f64, f64)>, Vec<(f64, f64)>), io::Error> {
@@ -51,45 +63,154 @@ pub fn read_elevation(ulid: &str) -> Result<(Vec<(f64, f64)>, Vec<(f64, f64)>),
 }
 
 pub fn read_track_notes(ulid: &str) -> Option<Vec<TrackNote>> {
-    let path = paths::track_notes(ulid);
-    match fs::read_to_string(path) {
-        Ok(s) => {
-            let notes = serde_json::from_str(&s.as_str()).unwrap();
-            return Some(notes)
-        }
+    match migrate::track_notes(ulid) {
+        Ok(notes) => Some(notes),
         Err(e) => {
-            println!("No track notes read for {}:\n{}", ulid, e);
-            return None
+            println!("No track notes read for {}:\n{:?}", ulid, e);
+            None
         }
     }
 }
 
+pub fn read_sensor_data(ulid: &str) -> Result<SensorData, io::Error> {
+    let path = paths::track_sensors(ulid);
+    let json_string = fs::read_to_string(path)?;
+    let data: SensorData = serde_json::from_str(&json_string.as_str())?;
+    Ok(data)
+}
+
+pub fn read_laps(ulid: &str) -> Result<Vec<Lap>, io::Error> {
+    let path = paths::track_laps(ulid);
+    let json_string = fs::read_to_string(path)?;
+    let laps: Vec<Lap> = serde_json::from_str(&json_string.as_str())?;
+    Ok(laps)
+}
+
+pub fn read_multisport_event(id: &str) -> Result<MultisportEvent, io::Error> {
+    let path = paths::multisport_event(id);
+    let json_string = fs::read_to_string(path)?;
+    let event: MultisportEvent = serde_json::from_str(&json_string.as_str())?;
+    Ok(event)
+}
+
+/// Returns the source file description and the content of the original file
+pub fn read_source(ulid: &str) -> Result<(SourceFile, Vec<u8>), io::Error> {
+    let json_string = fs::read_to_string(paths::track_source(ulid))?;
+    let file: SourceFile = serde_json::from_str(&json_string.as_str())?;
+    let data = fs::read(paths::track_original(ulid, &file.format))?;
+    Ok((file, data))
+}
+
+pub fn read_route(ulid: &str) -> Result<Route, io::Error> {
+    let json_string = fs::read_to_string(paths::route_analysis(ulid))?;
+    let route: Route = serde_json::from_str(&json_string.as_str())?;
+    Ok(route)
+}
+
+pub fn read_route_geojson(ulid: &str) -> Result<GeoJson, io::Error> {
+    let json_string = fs::read_to_string(paths::route_geojson(ulid))?;
+    json_string.parse::<GeoJson>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+pub fn read_route_elevation(ulid: &str) -> Result<(Vec<(f64, f64)>, Vec<(f64, f64)>), io::Error> {
+    let json_string = fs::read_to_string(paths::route_elevation(ulid))?;
+    let (elevation, coords): (Vec<(f64, f64)>, Vec<(f64, f64)>) = serde_json::from_str(&json_string.as_str())?;
+    Ok((elevation, coords))
+}
+
+pub fn read_pois() -> Result<Vec<Poi>, io::Error> {
+    let json_string = fs::read_to_string(paths::pois())?;
+    let pois: Vec<Poi> = serde_json::from_str(&json_string.as_str())?;
+    Ok(pois)
+}
+
 pub fn write_elevation(elevation: Vec<(f64, f64)>, coords: Vec<(f64, f64)>, ulid: &str) -> Result<(), io::Error> {
     let path = paths::track_elevation(ulid);
-    write_file(path, serde_json::to_string(&vec![elevation, coords])?).unwrap();
+    write_file(path, serde_json::to_string(&vec![elevation, coords])?)?;
     Ok(())
 }
 
 pub fn write_track_notes(notes: Vec<TrackNote>, ulid: &str) -> Result<(), io::Error> {
-    let path = paths::track_notes(ulid);
-    write_file(path, serde_json::to_string(&notes)?).unwrap();
+    let file = TrackNotes { version: crate::NOTES_VERSION, notes };
+    write_track_notes_file(&file, ulid)?;
+    if let Err(e) = index::update_notes(ulid, &file.notes) {
+        println!("{:?}", e);
+    }
     Ok(())
 }
 
+pub fn write_track_notes_file(file: &TrackNotes, ulid: &str) -> Result<(), io::Error> {
+    write_file(paths::track_notes(ulid), serde_json::to_string(file)?)
+}
+
 pub fn write_track_analysis(ta: &TrackAnalysis) -> Result<(), io::Error> {
-    let path = paths::track_analysis(&ta.ulid);
-    write_file(path, serde_json::to_string(ta)?)?;
+    write_track_analysis_file(ta)?;
+    // the folder stays the source of truth, a stale index is fixed by the next sync
+    if let Err(e) = index::update(ta) {
+        println!("{:?}", e);
+    }
     Ok(())
 }
 
-pub fn write_gpx(gpx: &Gpx, ulid: &str) -> Result<(), io::Error> {
-    let path = paths::track_gpx(ulid);
-    let file = File::create(path)?;
-    let writer = BufWriter::new(file);
-    gpx::write(gpx, writer).unwrap();
+/// Writes analysis.json without updating the index (used while the index is synced)
+pub fn write_track_analysis_file(ta: &TrackAnalysis) -> Result<(), io::Error> {
+    write_file(paths::track_analysis(&ta.ulid), serde_json::to_string(ta)?)
+}
+
+pub fn write_sensor_data(data: &SensorData, ulid: &str) -> Result<(), io::Error> {
+    let path = paths::track_sensors(ulid);
+    write_file(path, serde_json::to_string(data)?)?;
     Ok(())
 }
 
+pub fn write_laps(laps: &Vec<Lap>, ulid: &str) -> Result<(), io::Error> {
+    let path = paths::track_laps(ulid);
+    write_file(path, serde_json::to_string(laps)?)?;
+    Ok(())
+}
+
+pub fn write_multisport_event(event: &MultisportEvent) -> Result<(), io::Error> {
+    let path = paths::multisport_event(&event.id);
+    write_file(path, serde_json::to_string(event)?)?;
+    Ok(())
+}
+
+pub fn write_source(file: &SourceFile, data: &[u8], ulid: &str) -> Result<(), io::Error> {
+    write_atomic(&paths::track_original(ulid, &file.format), data)?;
+    write_file(paths::track_source(ulid), serde_json::to_string(file)?)?;
+    Ok(())
+}
+
+pub fn write_route(route: &Route) -> Result<(), io::Error> {
+    write_file(paths::route_analysis(&route.ulid), serde_json::to_string(route)?)
+}
+
+pub fn write_route_elevation(elevation: Vec<(f64, f64)>, coords: Vec<(f64, f64)>, ulid: &str) -> Result<(), io::Error> {
+    write_file(paths::route_elevation(ulid), serde_json::to_string(&(elevation, coords))?)
+}
+
+pub fn write_route_geojson(geojson: &GeoJson, ulid: &str) -> Result<(), io::Error> {
+    write_file(paths::route_geojson(ulid), geojson.to_string())
+}
+
+pub fn write_route_gpx(gpx: &Gpx, ulid: &str) -> Result<(), io::Error> {
+    write_atomic(&paths::route_gpx(ulid), &gpx_bytes(gpx)?)
+}
+
+pub fn write_pois(pois: &Vec<Poi>) -> Result<(), io::Error> {
+    write_file(paths::pois(), serde_json::to_string(pois)?)
+}
+
+pub fn write_gpx(gpx: &Gpx, ulid: &str) -> Result<(), io::Error> {
+    write_atomic(&paths::track_gpx(ulid), &gpx_bytes(gpx)?)
+}
+
+fn gpx_bytes(gpx: &Gpx) -> Result<Vec<u8>, io::Error> {
+    let mut content: Vec<u8> = vec![];
+    gpx::write(gpx, &mut content).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    Ok(content)
+}
+
 pub fn write_geojson(geojson: &GeoJson, ulid: &str) -> Result<(), io::Error> {
     let path = paths::track_geojson(ulid);
     write_file(path, geojson.to_string())?;
@@ -97,18 +218,46 @@ pub fn write_geojson(geojson: &GeoJson, ulid: &str) -> Result<(), io::Error> {
 }
 
 fn write_file(path: PathBuf, content: String) -> Result<(), io::Error> {
+    write_atomic(&path, content.as_bytes())
+}
+
+/// Writes to a temp file next to the target and renames it, so a crash never
+/// leaves a truncated file behind. Leftover temp files are removed by the
+/// startup check (see repair).
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<(), io::Error> {
     if let Some(p) = path.parent() {
         fs::create_dir_all(p)?
     };
-    fs::write(path, content)?;
-/*
-    let mut file = File::create(path).unwrap();
-    write!(file, "{}", content)?;
-    Ok(())
-    */
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!("{}{}", TEMP_SUFFIX, Ulid::new()));
+    let temp_path = PathBuf::from(temp_path);
+    let result = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(content)?;
+        file.sync_all()
+    }).and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
+    }
+    result
+}
+
+/// Marks the track as being written until end_write. Folders still marked on
+/// startup were interrupted and are rebuilt from record.gpx (see repair).
+pub fn begin_write(ulid: &str) -> Result<(), io::Error> {
+    fs::create_dir_all(paths::track(ulid))?;
+    File::create(paths::track_writing(ulid))?;
     Ok(())
 }
 
+pub fn end_write(ulid: &str) -> Result<(), io::Error> {
+    fs::remove_file(paths::track_writing(ulid))
+}
+
+/// True for temp files of write_atomic
+pub fn is_temp_file(path: &Path) -> bool {
+    path.file_name().and_then(|n| n.to_str()).map(|n| n.contains(TEMP_SUFFIX)).unwrap_or(false)
+}
+
 // TODO: handle panics!
 #[tokio::main]
 pub async fn download_tiff_zip(addr: &String, zip_path: PathBuf, out_path: PathBuf) -> Result<(), reqwest::Error> {
@@ -0,0 +1,176 @@
+use std::io::{Cursor, Read, Seek};
+
+use chrono::prelude::{DateTime, Utc};
+use geo_types::Point;
+use gpx::{Gpx, Time, Track, TrackSegment, Waypoint};
+use time::OffsetDateTime;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::errors::MaplineError;
+use crate::import::{self, ParsedTrack};
+use crate::importer::{self, FormatInfo, Importer};
+use crate::route::Planned;
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+
+/// Reads kml files and kmz archives (detected by their magic bytes)
+pub struct KmlImporter;
+
+impl Importer for KmlImporter {
+    fn info(&self) -> FormatInfo {
+        FormatInfo { id: "kml", name: "KML/KMZ", extensions: &["kml", "kmz"], column_mapping: false }
+    }
+
+    /// Zip archives are only detected as kmz if they contain a kml document
+    fn detect(&self, data: &[u8]) -> bool {
+        if data.starts_with(&ZIP_MAGIC) {
+            return match zip::ZipArchive::new(Cursor::new(data)) {
+                Ok(archive) => archive.file_names().any(|n| n.to_lowercase().ends_with(".kml")),
+                Err(_e) => false,
+            };
+        }
+        importer::xml_root(data, "<kml")
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<Vec<ParsedTrack>, MaplineError> {
+        Ok(self.parse_with_planned(data)?.0)
+    }
+
+    /// Every placemark becomes its own track, placemarks without timestamps
+    /// are planned routes
+    fn parse_with_planned(&self, data: &[u8]) -> Result<(Vec<ParsedTrack>, Planned), MaplineError> {
+        let gpxs = if data.starts_with(&ZIP_MAGIC) {
+            read_kmz(Cursor::new(data))?
+        } else {
+            read(Cursor::new(data))?
+        };
+        let (gpxs, planned) = Planned::split_untimed(gpxs);
+        Ok((import::untyped(gpxs), planned))
+    }
+}
+
+/// Reads every placemark with a LineString or gx:Track as separate gpx. Timed
+/// gx:Tracks keep their timestamps, LineStrings are imported without time (routes).
+pub fn read<R: Read>(reader: R) -> Result<Vec<Gpx>, MaplineError> {
+    let mut result: Vec<Gpx> = vec![];
+    let mut stack: Vec<String> = vec![];
+
+    let mut track: Option<Track> = None;
+    // gx:Track stores timestamps and coordinates in separate lists
+    let mut whens: Vec<Option<Time>> = vec![];
+    let mut coords: Vec<Waypoint> = vec![];
+
+    for e in EventReader::new(reader) {
+        match e {
+            Ok(XmlEvent::StartElement { name, .. }) => {
+                match name.local_name.as_str() {
+                    "Placemark" => track = Some(Track::new()),
+                    "LineString" => {
+                        if let Some(t) = track.as_mut() {
+                            t.segments.push(TrackSegment::new());
+                        }
+                    }
+                    "Track" => {
+                        whens = vec![];
+                        coords = vec![];
+                    }
+                    _ => (),
+                }
+                stack.push(name.local_name);
+            }
+            Ok(XmlEvent::Characters(text)) => {
+                let tag = stack.last().map(|s| s.as_str()).unwrap_or("");
+                let parent = if stack.len() > 1 { stack[stack.len() - 2].as_str() } else { "" };
+                let t = match track.as_mut() {
+                    Some(t) => t,
+                    None => continue,
+                };
+                match (parent, tag) {
+                    ("Placemark", "name") => t.name = Some(text.trim().to_string()),
+                    ("Placemark", "description") => t.description = Some(text.trim().to_string()),
+                    ("LineString", "coordinates") => {
+                        if let Some(s) = t.segments.last_mut() {
+                            s.points.extend(text.split_whitespace().filter_map(|c| parse_coord(c, ',')));
+                        }
+                    }
+                    ("Track", "when") => whens.push(parse_time(text.trim())),
+                    ("Track", "coord") => {
+                        if let Some(w) = parse_coord(text.trim(), ' ') {
+                            coords.push(w);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Ok(XmlEvent::EndElement { name }) => {
+                stack.pop();
+                match name.local_name.as_str() {
+                    "Track" => {
+                        if let Some(t) = track.as_mut() {
+                            let mut segment = TrackSegment::new();
+                            for (i, mut w) in coords.drain(..).enumerate() {
+                                w.time = whens.get(i).cloned().flatten();
+                                segment.points.push(w);
+                            }
+                            t.segments.push(segment);
+                        }
+                    }
+                    "Placemark" => {
+                        if let Some(t) = track.take() {
+                            if t.segments.iter().any(|s| !s.points.is_empty()) {
+                                let mut gpx = Gpx::default();
+                                gpx.version = gpx::GpxVersion::Gpx11;
+                                gpx.tracks.push(t);
+                                result.push(gpx);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Err(e) => return Err(MaplineError::ParseError(e.to_string())),
+            _ => (),
+        }
+    }
+    if result.is_empty() {
+        return Err(MaplineError::NoTrackPoints);
+    }
+    Ok(result)
+}
+
+/// Reads the first .kml document of a kmz archive
+pub fn read_kmz<R: Read + Seek>(reader: R) -> Result<Vec<Gpx>, MaplineError> {
+    let mut archive = match zip::ZipArchive::new(reader) {
+        Err(e) => return Err(MaplineError::ParseError(e.to_string())),
+        Ok(a) => a,
+    };
+    for i in 0..archive.len() {
+        let file = match archive.by_index(i) {
+            Err(e) => return Err(MaplineError::ParseError(e.to_string())),
+            Ok(f) => f,
+        };
+        if file.name().to_lowercase().ends_with(".kml") {
+            return read(file);
+        }
+    }
+    Err(MaplineError::ParseError("kmz archive does not contain a kml file".to_string()))
+}
+
+/// Parses "lon,lat[,alt]" (LineString) or "lon lat [alt]" (gx:coord)
+fn parse_coord(coord: &str, separator: char) -> Option<Waypoint> {
+    let values: Vec<f64> = coord.split(separator)
+        .filter(|v| !v.is_empty())
+        .filter_map(|v| v.parse::<f64>().ok())
+        .collect();
+    if values.len() < 2 {
+        return None;
+    }
+    let mut w = Waypoint::new(Point::new(values[0], values[1]));
+    w.elevation = values.get(2).cloned();
+    Some(w)
+}
+
+fn parse_time(value: &str) -> Option<Time> {
+    let ti = value.parse::<DateTime<Utc>>().ok()?;
+    Some(Time::from(OffsetDateTime::from_unix_timestamp(ti.timestamp()).ok()?))
+}
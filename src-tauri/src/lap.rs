@@ -0,0 +1,43 @@
+use gpx::Gpx;
+use serde::{Serialize, Deserialize};
+use time::OffsetDateTime;
+
+use crate::segment;
+
+/// Lap as recorded by the device (lap button or auto lap)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Lap {
+    pub start_index: Option<usize>, // None for laps without points (e.g. pool swimming)
+    pub end_index: Option<usize>,
+    pub start_time: Option<String>,
+    pub duration_sec: Option<f64>, // timer time, without pauses
+    pub distance: Option<f64>, // in meters
+    pub avg_vel: Option<f64>, // in kph
+    pub avg_heart_rate: Option<f64>,
+    pub avg_cadence: Option<f64>,
+    pub avg_power: Option<f64>,
+}
+
+/// Returns the indices of the first and the last point recorded between start and end
+/// (unix timestamps), None if there is no point in between.
+pub fn index_range(gpx: &Gpx, start: i64, end: i64) -> Option<(usize, usize)> {
+    let mut start_index: Option<usize> = None;
+    let mut end_index = 0;
+    for (i, p) in segment::points(gpx).into_iter().enumerate() {
+        let t = match p.time {
+            Some(t) => OffsetDateTime::from(t).unix_timestamp(),
+            None => continue,
+        };
+        if t < start {
+            continue;
+        }
+        if t > end {
+            break;
+        }
+        if start_index.is_none() {
+            start_index = Some(i);
+        }
+        end_index = i;
+    }
+    start_index.map(|s| (s, end_index))
+}
@@ -0,0 +1,266 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{io, paths};
+use crate::errors::MaplineError;
+
+/// Path or name of the library to open, takes precedence over the library
+/// chosen in the app
+const LIBRARY_ENV: &str = "MAPLINES_LIBRARY";
+/// Same as the environment variable, e.g. --library /mnt/nas/surveys or --library=Surveys
+const LIBRARY_ARG: &str = "--library";
+const DEFAULT_NAME: &str = "Default";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Library {
+    pub name: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub pending_move: Option<PathBuf>, // new location, the library is moved on the next start
+}
+
+/// Content of maplines_libraries.json
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Libraries {
+    pub version: i32,
+    pub current: String, // name of the library opened on startup
+    pub libraries: Vec<Library>,
+}
+
+/// Open library and all known libraries, for the ui
+#[derive(Serialize, Clone, Debug)]
+pub struct LibraryInfo {
+    pub path: PathBuf,
+    pub name: Option<String>, // None for a path given by argument or environment variable
+    pub overridden: bool,
+    pub libraries: Vec<Library>,
+}
+
+impl Libraries {
+    /// Installations before libraries could be configured have one library at
+    /// the old location
+    fn new() -> Libraries {
+        Libraries {
+            version: crate::LIBRARIES_VERSION,
+            current: DEFAULT_NAME.to_string(),
+            libraries: vec![Library { name: DEFAULT_NAME.to_string(), path: paths::default_library(), pending_move: None }],
+        }
+    }
+
+    pub fn load() -> Result<Libraries, MaplineError> {
+        let path = paths::libraries();
+        if !path.exists() {
+            let libraries = Libraries::new();
+            libraries.write()?;
+            return Ok(libraries);
+        }
+        let json_string = fs::read_to_string(path).map_err(|e| MaplineError::FileError(e.to_string()))?;
+        serde_json::from_str(&json_string).map_err(|e| MaplineError::ParseError(e.to_string()))
+    }
+
+    fn write(&self) -> Result<(), MaplineError> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+        io::write_atomic(&paths::libraries(), content.as_bytes()).map_err(|e| MaplineError::WriteError(e.to_string()))
+    }
+
+    fn find(&self, name: &str) -> Option<&Library> {
+        self.libraries.iter().find(|l| l.name == name)
+    }
+
+    fn find_mut(&mut self, name: &str) -> Result<&mut Library, MaplineError> {
+        self.libraries.iter_mut()
+            .find(|l| l.name == name)
+            .ok_or_else(|| MaplineError::LibraryError(format!("no library named {}", name)))
+    }
+}
+
+/// Chooses the library and sets it as root of all paths, called on startup
+/// before anything is read. Pending moves are done first, while no file of
+/// the library is in use.
+pub fn open(args: &[String]) -> LibraryInfo {
+    let mut libraries = Libraries::load().unwrap_or_else(|e| {
+        println!("Could not read the libraries, using the default library: {:?}", e);
+        Libraries::new()
+    });
+    apply_pending_moves(&mut libraries);
+
+    let info = match override_value(args) {
+        Some(value) => {
+            // a name of a known library or a path
+            let library = libraries.find(&value).cloned();
+            LibraryInfo {
+                path: library.as_ref().map(|l| l.path.clone()).unwrap_or_else(|| PathBuf::from(&value)),
+                name: library.map(|l| l.name),
+                overridden: true,
+                libraries: libraries.libraries.clone(),
+            }
+        }
+        None => {
+            let library = libraries.find(&libraries.current).cloned()
+                .unwrap_or_else(|| Library { name: DEFAULT_NAME.to_string(), path: paths::default_library(), pending_move: None });
+            LibraryInfo {
+                path: library.path,
+                name: Some(library.name),
+                overridden: false,
+                libraries: libraries.libraries.clone(),
+            }
+        }
+    };
+    paths::set_maplines(info.path.clone());
+    info
+}
+
+/// Value of --library or of the environment variable, the argument wins
+fn override_value(args: &[String]) -> Option<String> {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == LIBRARY_ARG {
+            return iter.next().cloned();
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{}=", LIBRARY_ARG)) {
+            return Some(value.to_string());
+        }
+    }
+    std::env::var(LIBRARY_ENV).ok().filter(|v| !v.is_empty())
+}
+
+pub fn info() -> Result<LibraryInfo, MaplineError> {
+    let libraries = Libraries::load()?;
+    let args: Vec<String> = std::env::args().collect();
+    let overridden = override_value(&args).is_some();
+    let root = paths::maplines();
+    Ok(LibraryInfo {
+        name: libraries.libraries.iter().find(|l| l.path == root).map(|l| l.name.clone()),
+        path: root,
+        overridden,
+        libraries: libraries.libraries,
+    })
+}
+
+/// Adds a library, an existing folder is used as it is
+pub fn add(name: &str, path: &Path) -> Result<Library, MaplineError> {
+    let mut libraries = Libraries::load()?;
+    if libraries.find(name).is_some() {
+        return Err(MaplineError::LibraryError(format!("library {} already exists", name)));
+    }
+    fs::create_dir_all(path).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    let library = Library { name: name.to_string(), path: path.to_path_buf(), pending_move: None };
+    libraries.libraries.push(library.clone());
+    libraries.write()?;
+    Ok(library)
+}
+
+/// Sets the library opened on the next start. The app has to be restarted,
+/// so the watcher, the index and all paths change together.
+pub fn switch(name: &str) -> Result<(), MaplineError> {
+    let args: Vec<String> = std::env::args().collect();
+    if override_value(&args).is_some() {
+        return Err(MaplineError::LibraryError(format!("the library is set by {} or {}", LIBRARY_ARG, LIBRARY_ENV)));
+    }
+    let mut libraries = Libraries::load()?;
+    libraries.find_mut(name)?;
+    libraries.current = name.to_string();
+    libraries.write()
+}
+
+/// Moves the library to a new, empty folder. The open library is moved on the
+/// next start, returns true in that case so the app can be restarted.
+pub fn relocate(name: &str, target: &Path) -> Result<bool, MaplineError> {
+    let mut libraries = Libraries::load()?;
+    let open = libraries.find_mut(name)?.path == paths::maplines();
+    let library = libraries.find_mut(name)?;
+    check_target(&library.path, target)?;
+    if open {
+        library.pending_move = Some(target.to_path_buf());
+    } else {
+        move_dir(&library.path, target)?;
+        library.path = target.to_path_buf();
+    }
+    libraries.write()?;
+    Ok(open)
+}
+
+fn apply_pending_moves(libraries: &mut Libraries) {
+    let mut changed = false;
+    for library in libraries.libraries.iter_mut() {
+        if let Some(target) = library.pending_move.take() {
+            changed = true;
+            match check_target(&library.path, &target).and_then(|_| move_dir(&library.path, &target)) {
+                Ok(()) => library.path = target,
+                Err(e) => println!("Could not move library {}: {:?}", library.name, e),
+            }
+        }
+    }
+    if changed {
+        if let Err(e) = libraries.write() {
+            println!("{:?}", e);
+        }
+    }
+}
+
+fn check_target(from: &Path, to: &Path) -> Result<(), MaplineError> {
+    if to.starts_with(from) {
+        return Err(MaplineError::LibraryError("a library can not be moved into itself".to_string()));
+    }
+    let empty = fs::read_dir(to).map(|mut d| d.next().is_none()).unwrap_or(true);
+    if !empty {
+        return Err(MaplineError::LibraryError(format!("{} is not empty", to.to_string_lossy())));
+    }
+    Ok(())
+}
+
+/// Renames the folder if possible. Across drives (e.g. to a network share)
+/// the files are copied and compared, the old folder is only removed once
+/// the copy is complete.
+fn move_dir(from: &Path, to: &Path) -> Result<(), MaplineError> {
+    let write_error = |e: std::io::Error| MaplineError::WriteError(e.to_string());
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(write_error)?;
+    }
+    // an empty target folder is replaced by the library
+    if to.exists() {
+        fs::remove_dir(to).map_err(write_error)?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if let Err(e) = copy_dir(from, to).map_err(write_error).and_then(|_| compare_dirs(from, to)) {
+        fs::remove_dir_all(to).ok();
+        return Err(e);
+    }
+    fs::remove_dir_all(from).map_err(write_error)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fails if a file of from is missing in to or differs in size
+fn compare_dirs(from: &Path, to: &Path) -> Result<(), MaplineError> {
+    let entries = fs::read_dir(from).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| MaplineError::FileError(e.to_string()))?;
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            compare_dirs(&entry.path(), &target)?;
+            continue;
+        }
+        let size = |p: &Path| fs::metadata(p).map(|m| m.len()).ok();
+        if size(&entry.path()).is_none() || size(&entry.path()) != size(&target) {
+            return Err(MaplineError::LibraryError(format!("{} was not copied", entry.path().to_string_lossy())));
+        }
+    }
+    Ok(())
+}
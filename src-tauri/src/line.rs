@@ -1,7 +1,8 @@
-use gpx::{Gpx, Track, TrackSegment};
+use gpx::Gpx;
 use geojson::{self, Feature, GeoJson, Geometry, JsonObject, JsonValue, Value, PointType, LineStringType};
 
 use crate::pause::Pause;
+use crate::segment;
 
 pub fn arrange_display(gpx: &Gpx, geojson_orig: Option<GeoJson>, pauses: Option<&Vec<Pause>>) -> GeoJson {
     let geojson = match pauses {
@@ -19,21 +20,21 @@ pub fn arrange_display(gpx: &Gpx, geojson_orig: Option<GeoJson>, pauses: Option<
 }
 
 fn compose(gpx: &Gpx, name: &str) -> GeoJson {
-    // TODO: handle multiple tracks or segments
-    let track: &Track = &gpx.tracks[0];
-    let segment: &TrackSegment = &track.segments[0];
-    
     let mut properties = JsonObject::new();
     properties.insert(
         String::from("name"),
         JsonValue::from(name),
     );
     
-    let mut line_string: Vec<PointType> = vec![];
-    for w in segment.points.iter() {
-        line_string.push(vec![w.point().x(), w.point().y()]);
+    // one line per segment of every track
+    let mut lines: Vec<LineStringType> = vec![];
+    for s in gpx.tracks.iter().flat_map(|t| t.segments.iter()) {
+        if s.points.is_empty() {
+            continue;
+        }
+        lines.push(s.points.iter().map(|w| vec![w.point().x(), w.point().y()]).collect());
     }
-    let geometry = Geometry::new(Value::LineString(line_string));
+    let geometry = Geometry::new(Value::MultiLineString(lines));
 
     let geojson = GeoJson::Feature(Feature {
         bbox: None,
@@ -52,9 +53,6 @@ fn compose(gpx: &Gpx, name: &str) -> GeoJson {
 }
 
 fn compose_with_pauses(gpx: &Gpx, pauses: &Vec<Pause>) -> GeoJson {
-    let track: &Track = &gpx.tracks[0];
-    let segment: &TrackSegment = &track.segments[0];
-    
     let mut properties = JsonObject::new();
     properties.insert(
         String::from("name"),
@@ -69,7 +67,7 @@ fn compose_with_pauses(gpx: &Gpx, pauses: &Vec<Pause>) -> GeoJson {
     let mut line_pause: Vec<PointType> = vec![];
     let mut line_uned_pause: Vec<PointType> = vec![];
     let mut pause_pos = 0;
-    for (i, w) in segment.points.iter().enumerate() {
+    for (i, w) in segment::points(gpx).into_iter().enumerate() {
         if pause_pos == pauses.len() {
             line_move.push(vec![w.point().x(), w.point().y()]);
         }
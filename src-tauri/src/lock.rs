@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Managed by tauri, one lock per track, so commands changing the same track
+/// run one after another
+#[derive(Default)]
+pub struct TrackLocks(Mutex<HashMap<String, Arc<Mutex<()>>>>);
+
+impl TrackLocks {
+    /// Lock of the track, to be held while its files are read and written
+    pub fn get(&self, ulid: &str) -> Arc<Mutex<()>> {
+        self.0.lock().unwrap().entry(ulid.to_string()).or_default().clone()
+    }
+
+    /// Runs f while holding the locks of all tracks. The locks are taken in
+    /// sorted order, so two calls with the same tracks can not deadlock.
+    pub fn with<T>(&self, ulids: &[String], f: impl FnOnce() -> T) -> T {
+        let mut sorted: Vec<&String> = ulids.iter().collect();
+        sorted.sort();
+        sorted.dedup();
+        let locks: Vec<Arc<Mutex<()>>> = sorted.iter().map(|u| self.get(u)).collect();
+        let _guards: Vec<MutexGuard<()>> = locks.iter().map(|l| acquire(l)).collect();
+        f()
+    }
+}
+
+/// A panic while holding the lock leaves the files as they are, the lock itself
+/// stays usable
+pub fn acquire(lock: &Mutex<()>) -> MutexGuard<()> {
+    lock.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -19,8 +19,12 @@ extern crate byteorder;
 extern crate enum_primitive;
 extern crate num;
 extern crate zip;
+extern crate xml;
+extern crate notify;
 
 mod import;
+mod importer;
+mod fit;
 mod io;
 mod geotiff;
 mod track_analysis;
@@ -33,134 +37,374 @@ mod settings;
 mod util;
 mod elevation;
 mod distance;
+mod tcx;
+mod kml;
+mod strava;
+mod multisport;
+mod source;
+mod watcher;
+mod segment;
+mod sensor;
+mod summary;
+mod lap;
+mod igc;
+mod flight;
+mod geojson_track;
+mod csv_track;
+mod duplicate;
+mod report;
+mod batch;
+mod route;
+mod index;
+mod migrate;
+mod lock;
+mod repair;
+mod trash;
+mod history;
+mod library;
 
-use std::ffi::OsStr;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use batch::BatchState;
+use csv_track::CsvColumns;
 use geojson::GeoJson;
+use history::{Journal, Operation};
+use elevation::ElevationProfiles;
+use errors::MaplineError;
+use index::TrackFilter;
+use lap::Lap;
+use library::LibraryInfo;
+use lock::TrackLocks;
+use multisport::MultisportEvent;
 use pause::Pause;
+use repair::RepairReport;
+use report::{ImportReport, RetryFile};
+use route::{Poi, Route, RouteComparison};
 use track_analysis::TrackAnalysis;
+use sensor::SensorData;
 use settings::Settings;
 use tauri::api::{dialog};
-use tauri::{CustomMenuItem, Menu, Submenu};
+use tauri::{CustomMenuItem, FileDropEvent, Manager, Menu, MenuItem, Submenu, WindowEvent};
+use watcher::{WatchCommand, WatcherState};
 use track_note::TrackNote;
 use ulid::Ulid;
 
-const ANALYSIS_VERSION: i32 = 1;
-const SETTINGS_VERSION: i32 = 1;
+const ANALYSIS_VERSION: i32 = 2;
+const SETTINGS_VERSION: i32 = 2;
+const NOTES_VERSION: i32 = 2;
+const JOURNAL_VERSION: i32 = 1;
+const LIBRARIES_VERSION: i32 = 1;
+
+/// Menu ids of the libraries, followed by the name of the library
+const LIBRARY_MENU_PREFIX: &str = "library:";
 
 fn main() {
+  // the library is chosen before anything is read from it
+  let args: Vec<String> = std::env::args().collect();
+  let library = library::open(&args);
+  println!("Library {}", library.path.to_string_lossy());
   paths::create_dirs_if_not_exist();
+  let locks = TrackLocks::default();
+  // folders of interrupted writes are repaired and old analyses upgraded before
+  // they are indexed
+  let report = repair::check_library(&locks);
+  if !report.is_clean() {
+    println!("{:?}", report);
+  }
+  // picks up tracks changed or removed outside of the app
+  if let Err(e) = index::sync() {
+    println!("{:?}", e);
+  }
+  match Settings::load() {
+    Ok(settings) => {
+      let purged = trash::purge_expired(settings.trash_days, &locks);
+      if !purged.is_empty() {
+        println!("Purged {} tracks from the trash", purged.len());
+      }
+    }
+    Err(e) => println!("{:?}", e),
+  }
   //let mut settings: Mutex<Settings> = Mutex::new(Settings::load().unwrap());
   let version_item = CustomMenuItem::new("version".to_string(), "Version");
   let main_menu = Submenu::new("Main", Menu::new()
   .add_item(version_item));
-  let import_gpx = CustomMenuItem::new("gpx".to_string(), "Import GPX Files...");
-  let import_fit = CustomMenuItem::new("fit".to_string(), "Import FIT Files...");
+  let import_strava = CustomMenuItem::new("strava".to_string(), "Import Strava Export...");
   let import_path = CustomMenuItem::new("path".to_string(), "Add Import Path...");
   let import_direct = CustomMenuItem::new("direct".to_string(), "Import From Paths");
-  let open_menu = Submenu::new("Open", Menu::new().add_item(import_gpx).add_item(import_fit).add_item(import_path).add_item(import_direct));
+  // one item per importer, the menu id is the id of the format
+  let mut open_items = Menu::new();
+  for i in importer::registry() {
+    let info = i.info();
+    open_items = open_items.add_item(CustomMenuItem::new(info.id.to_string(), format!("Import {} Files...", info.name)));
+  }
+  let open_menu = Submenu::new("Open", open_items.add_item(import_strava).add_item(import_path).add_item(import_direct));
+  // switching restarts the app, the menu is built again with the new library
+  let mut library_items = Menu::new();
+  for l in &library.libraries {
+    let mut item = CustomMenuItem::new(format!("{}{}", LIBRARY_MENU_PREFIX, l.name), l.name.clone());
+    if library.name.as_ref() == Some(&l.name) {
+      item = item.selected();
+    }
+    if library.overridden {
+      item = item.disabled();
+    }
+    library_items = library_items.add_item(item);
+  }
+  let add_library = CustomMenuItem::new("add_library".to_string(), "Add Library...");
+  let move_library = CustomMenuItem::new("move_library".to_string(), "Move Library...");
+  let library_menu = Submenu::new("Library", library_items.add_native_item(MenuItem::Separator).add_item(add_library).add_item(move_library));
 
   tauri::Builder::default()
-    .menu(Menu::new().add_submenu(main_menu).add_submenu(open_menu))
+    .menu(Menu::new().add_submenu(main_menu).add_submenu(open_menu).add_submenu(library_menu))
     .on_menu_event(|event| match event.menu_item_id() {
       "version" => {
         println!("{}", option_env!("CARGO_PKG_VERSION").unwrap());
       }
-      "gpx" => {
-        dialog::FileDialogBuilder::default()
-        .add_filter("GPS", &["gpx"])
-        .pick_files(move |file_paths| {
-          match file_paths {
-            Some(vec_fp) => {
-              for fp in vec_fp {
-                let track_analysis = match import::gpx(&fp) {
-                  Err(_e) => { println!("File with same start time is already present."); return; },
-                  Ok(t) => t,
-                };
-                event.window().emit("track_import", track_analysis).unwrap();
-              }
-            }
-            _ => { dbg!("gpx file could not be imported."); },
-          }
-        })
-      }
-      "fit" => {
+      "strava" => {
         dialog::FileDialogBuilder::default()
-        .add_filter("FIT", &["FIT", "fit"])
-        .pick_files(move |file_paths| {
-          match file_paths {
-            Some(vec_fp) => {
-              for fp in vec_fp {
-                let track_analysis = import::fit(&fp).unwrap();
-                event.window().emit("track_import", track_analysis).unwrap();
-              }
-            }
-            _ => { dbg!("gpx file could not be imported."); },
+        .add_filter("Strava Export", &["zip"])
+        .pick_file(move |file_path| {
+          if let Some(fp) = file_path {
+            import_strava_archive(event.window().clone(), fp, None, false);
           }
         })
       }
       "path" => {
-        dialog::FileDialogBuilder::default().pick_folder(|dir_path| {
-          let mut settings = Settings::load().unwrap();
-          settings.add_path(dir_path.unwrap());
+        let sender = event.window().state::<WatcherState>().0.lock().unwrap().clone();
+        dialog::FileDialogBuilder::default().pick_folder(move |dir_path| {
+          if let Some(dir) = dir_path {
+            let mut settings = Settings::load().unwrap();
+            settings.add_path(dir.clone());
+            sender.send(WatchCommand::AddPath(dir)).unwrap();
+          }
         });
 
       }
       "direct" => {
-        let settings = Settings::load().unwrap();
-        std::thread::spawn(move || {
-          for p in settings.import_paths {
-            // import files
-            let paths: Vec<PathBuf> = std::fs::read_dir(p).unwrap()
-            .into_iter()
-            .map(|x| x.unwrap().path())
-            //.filter(|x| x.file_name().unwrap().to_str().unwrap().ends_with(".json"))
-            .collect();
-            for p in paths {
-              if p.extension() == Some(OsStr::new("fit")) || p.extension() == Some(OsStr::new("FIT")) {
-                
-                match import::fit(&p) {
-                  Ok(ta) => event.window().emit("track_import", ta).unwrap(),
-                  _ => ()
-                }
-                
-              } else if p.extension() == Some(OsStr::new("gpx")) {
-                let track_analysis = import::gpx(&p).unwrap();
-                event.window().emit("track_import", track_analysis).unwrap();
-              }
+        // files already seen in the import paths are skipped by the watcher
+        let sender = event.window().state::<WatcherState>().0.lock().unwrap();
+        sender.send(WatchCommand::Rescan).unwrap();
+      }
+      "add_library" => {
+        // the library is named after its folder and opened right away
+        let app = event.window().app_handle();
+        dialog::FileDialogBuilder::default().pick_folder(move |dir_path| {
+          if let Some(dir) = dir_path {
+            let name = match dir.file_name() {
+              Some(n) => n.to_string_lossy().to_string(),
+              None => dir.to_string_lossy().to_string(),
+            };
+            match library::add(&name, &dir).and_then(|_| library::switch(&name)) {
+              Ok(()) => tauri::api::process::restart(&app.env()),
+              Err(e) => println!("{:?}", e),
+            }
+          }
+        });
+      }
+      "move_library" => {
+        let app = event.window().app_handle();
+        dialog::FileDialogBuilder::default().pick_folder(move |dir_path| {
+          if let Some(dir) = dir_path {
+            if let Some(true) = move_open_library(&dir) {
+              tauri::api::process::restart(&app.env());
             }
-  
           }
         });
       }
+      id if id.starts_with(LIBRARY_MENU_PREFIX) => {
+        match library::switch(&id[LIBRARY_MENU_PREFIX.len()..]) {
+          Ok(()) => tauri::api::process::restart(&event.window().app_handle().env()),
+          Err(e) => println!("{:?}", e),
+        }
+      }
+      id => {
+        let info = match importer::find(id) {
+          Some(i) => i.info(),
+          None => return,
+        };
+        let mut extensions: Vec<String> = info.extensions.iter()
+          .flat_map(|e| vec![e.to_string(), e.to_uppercase()])
+          .collect();
+        extensions.push("gz".to_string());
+        let extensions: Vec<&str> = extensions.iter().map(|e| e.as_str()).collect();
+        dialog::FileDialogBuilder::default()
+        .add_filter(info.name, &extensions)
+        .pick_files(move |file_paths| {
+          if let Some(vec_fp) = file_paths {
+            if info.column_mapping {
+              // the columns are mapped by the user before the import
+              event.window().emit("csv_import", vec_fp).ok();
+            } else {
+              batch::import(event.window().clone(), vec_fp, false);
+            }
+          }
+        })
+      }
+    })
+    .setup(move |app| {
+      let window = app.get_window("main").unwrap();
+      app.manage(WatcherState(Mutex::new(watcher::start(window))));
+      app.manage(BatchState::default());
+      app.manage(locks);
+      Ok(())
+    })
+    .on_window_event(|event| match event.event() {
+      WindowEvent::FileDrop(FileDropEvent::Dropped(paths)) => {
+        // csv files are imported after the user mapped the columns, as from the menu
+        let (csv, other): (Vec<PathBuf>, Vec<PathBuf>) = paths.iter().cloned().partition(|p| {
+          importer::find(&import::extension(p)).map(|i| i.info().column_mapping).unwrap_or(false)
+        });
+        if !csv.is_empty() {
+          event.window().emit("csv_import", csv).ok();
+        }
+        if !other.is_empty() {
+          batch::import(event.window().clone(), other, false);
+        }
+      }
       _ => {}
     })
-    .invoke_handler(tauri::generate_handler![load_geojson, load_pauses, load_track_analysis, calculate_pauses, load_track_display_data, save_track_changes, load_elevation, load_notes, join_tracks, delete_track, recalculate_track, add_note])
+    .invoke_handler(tauri::generate_handler![load_geojson, load_pauses, load_track_analysis, calculate_pauses, load_track_display_data, save_track_changes, load_elevation, load_notes, join_tracks, delete_track, recalculate_track, add_note, load_sensor_data, load_laps, set_summary_source, load_multisport_event, reimport_track, retry_import, retry_archive_import, cancel_import, load_csv_columns, import_csv, load_routes, load_route_geojson, load_route_elevation, load_pois, link_route, compare_route, delete_route, set_elevation_source, load_elevation_profiles, filter_tracks, rebuild_index, load_tags, set_tags, check_library, load_trash, restore_track, purge_track, set_archived, load_history, undo_track, redo_track, load_libraries, add_library, switch_library, move_library])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
 
 }
 
+
+/// Imports files of a previous report again, with force duplicates are imported anyway
+#[tauri::command]
+fn retry_import(window: tauri::Window, files: Vec<RetryFile>, force: bool) {
+  batch::retry(window, files, force);
+}
+
+/// Same as retry_import for entries of a strava archive
+#[tauri::command]
+fn retry_archive_import(window: tauri::Window, archive: String, entries: Vec<RetryFile>, force: bool) {
+  import_strava_archive(window, PathBuf::from(archive), Some(entries), force);
+}
+
+fn import_strava_archive(window: tauri::Window, archive: PathBuf, entries: Option<Vec<RetryFile>>, force: bool) {
+  std::thread::spawn(move || {
+    let report = match strava::import(&archive, entries.as_deref(), force, |ta| { window.emit("track_import", ta).ok(); }) {
+      Ok(r) => r,
+      Err(e) => {
+        // the archive itself could not be read
+        let mut r = ImportReport::default();
+        r.add(&archive.to_string_lossy(), Err(e));
+        r
+      }
+    };
+    window.emit("import_report", report).ok();
+  });
+}
+
+/// Header names of the csv and the current column mapping
+#[tauri::command]
+fn load_csv_columns(path: String) -> Option<(Vec<String>, CsvColumns)> {
+  let data = import::read_file(&PathBuf::from(path)).ok()?;
+  let headers = csv_track::headers(&data).ok()?;
+  Some((headers, Settings::load().ok()?.csv_columns))
+}
+
+/// Stores the column mapping and imports the csv files
+#[tauri::command]
+fn import_csv(window: tauri::Window, paths: Vec<String>, columns: CsvColumns) {
+  if let Ok(mut settings) = Settings::load() {
+    settings.set_csv_columns(columns);
+  }
+  batch::import(window, paths.into_iter().map(PathBuf::from).collect(), false);
+}
+
+#[tauri::command]
+fn cancel_import(state: tauri::State<BatchState>) {
+  state.cancel();
+}
+
+#[tauri::command]
+fn load_routes() -> Vec<Route> {
+  route::load_routes()
+}
+
+#[tauri::command]
+fn load_route_geojson(ulid: String) -> Option<GeoJson> {
+  io::read_route_geojson(&ulid).ok()
+}
+
+#[tauri::command]
+fn load_route_elevation(ulid: String) -> Option<(Vec<(f64, f64)>, Vec<(f64, f64)>)> {
+  io::read_route_elevation(&ulid).ok()
+}
+
+#[tauri::command]
+fn load_pois() -> Vec<Poi> {
+  io::read_pois().unwrap_or_default()
+}
+
+/// Links the track to the planned route it followed, without route the link is removed
+#[tauri::command]
+fn link_route(locks: tauri::State<TrackLocks>, ulid: String, route: Option<String>) -> Option<TrackAnalysis> {
+  match locks.with(&[ulid.clone()], || history::edit(&ulid, Operation::Route, || route::link(&ulid, route))) {
+    Ok(ta) => Some(ta),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+/// Planned vs. actual distance and elevation of a track and its linked route
+#[tauri::command]
+fn compare_route(ulid: String) -> Option<RouteComparison> {
+  route::compare(&ulid)
+}
+
+#[tauri::command]
+fn delete_route(locks: tauri::State<TrackLocks>, ulid: String) {
+  if let Err(e) = route::delete(&ulid, &locks) {
+    println!("{:?}", e);
+  }
+}
+
 #[tauri::command]
 fn load_track_analysis() -> Vec<TrackAnalysis> {
-  let paths: Vec<PathBuf> = std::fs::read_dir(paths::tracks()).unwrap()
-  .into_iter()
-  .map(|x| x.unwrap().path())
-  // .filter(|x| x.file_name().unwrap().to_str().unwrap().ends_with(".json"))
-  .filter(|x| x.is_dir())
-  .collect();
-  let mut result: Vec<TrackAnalysis> = vec![];
-  for p in paths {
-    /*match TrackAnalysis::read(&p) {
-      Ok(ta) => result.push(ta),
-      _ => println!("Could not load track with path {:?}.", p),
-    }*/
-    match io::read_track_analysis(&p.file_name().unwrap().to_str().unwrap().to_string()) {
-      Ok(ta) => result.push(ta),
-      _ => println!("Could not load track with path {:?}.", p),
+  match index::list() {
+    Ok(analysis) => analysis,
+    Err(e) => {
+      println!("{:?}", e);
+      index::read_from_disk().into_iter().filter(|ta| !ta.archived && ta.trashed.is_none()).collect()
     }
   }
-  result
+}
+
+#[tauri::command]
+fn filter_tracks(filter: TrackFilter) -> Vec<TrackAnalysis> {
+  index::filter(&filter).unwrap_or_else(|e| { println!("{:?}", e); vec![] })
+}
+
+/// Reads all track folders into a new index, returns the number of tracks
+#[tauri::command]
+fn rebuild_index() -> Option<usize> {
+  match index::rebuild() {
+    Ok(count) => Some(count),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+#[tauri::command]
+fn load_tags() -> Vec<String> {
+  index::tags().unwrap_or_default()
+}
+
+#[tauri::command]
+fn set_tags(locks: tauri::State<TrackLocks>, ulid: String, tags: Vec<String>) -> Option<TrackAnalysis> {
+  let result = locks.with(&[ulid.clone()], || history::edit(&ulid, Operation::Tags, || {
+    let mut track_analysis = io::read_track_analysis(&ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    track_analysis.tags = tags.into_iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+    track_analysis.tags.sort();
+    track_analysis.tags.dedup();
+    io::write_track_analysis(&track_analysis).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    Ok(track_analysis)
+  }));
+  match result {
+    Ok(ta) => Some(ta),
+    Err(e) => { println!("{:?}", e); None },
+  }
 }
 
 #[tauri::command]
@@ -213,13 +457,45 @@ fn load_elevation(ulid: String) -> Option<(Vec<(f64, f64)>, Vec<(f64, f64)>)> {
   None
 }
 
+/// Returns the cumulative distance of every point and the recorded device channels
+#[tauri::command]
+fn load_sensor_data(ulid: String) -> Option<(Vec<f64>, SensorData)> {
+  let data = io::read_sensor_data(&ulid).ok()?;
+  let gpx = io::read_gpx(&ulid)?;
+  Some((sensor::point_distances(&gpx), data))
+}
+
+#[tauri::command]
+fn load_laps(ulid: String) -> Option<Vec<Lap>> {
+  io::read_laps(&ulid).ok()
+}
+
+#[tauri::command]
+fn load_multisport_event(id: String) -> Option<MultisportEvent> {
+  io::read_multisport_event(&id).ok()
+}
+
+#[tauri::command]
+fn set_summary_source(locks: tauri::State<TrackLocks>, ulid: String, source: String) -> Option<TrackAnalysis> {
+  let result = locks.with(&[ulid.clone()], || history::edit(&ulid, Operation::SummarySource, || {
+    let mut track_analysis = io::read_track_analysis(&ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    track_analysis.summary_source = summary::summary_source_from_string(&source);
+    io::write_track_analysis(&track_analysis).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    Ok(track_analysis)
+  }));
+  match result {
+    Ok(ta) => Some(ta),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
 #[tauri::command]
 fn load_notes(ulid: String) -> Option<Vec<TrackNote>> {
   io::read_track_notes(&ulid)
 }
 
 #[tauri::command]
-fn add_note(ulid: String, coords: (f64, f64), icon: String, comment: Option<String>, img_paths: Option<Vec<String>>) {
+fn add_note(locks: tauri::State<TrackLocks>, ulid: String, coords: (f64, f64), icon: String, comment: Option<String>, img_paths: Option<Vec<String>>) {
   println!("{:?}", img_paths);
   let img_paths = match img_paths {
     Some(p) => Some(p.iter().map(|p| PathBuf::from(p)).collect()),
@@ -233,6 +509,8 @@ fn add_note(ulid: String, coords: (f64, f64), icon: String, comment: Option<Stri
     pictures: img_paths.clone()
   };
 
+  let lock = locks.get(&ulid);
+  let _guard = lock::acquire(&lock);
   let mut notes;
   match io::read_track_notes(&ulid) {
     Some(n) => {
@@ -245,33 +523,193 @@ fn add_note(ulid: String, coords: (f64, f64), icon: String, comment: Option<Stri
 }
 
 #[tauri::command]
-fn save_track_changes(ulid: String, name: String, activity: String) {
+fn save_track_changes(window: tauri::Window, locks: tauri::State<TrackLocks>, ulid: String, name: String, activity: String) {
   println!("{}", activity);
-  let mut track_analysis = io::read_track_analysis(&ulid).unwrap();
-  track_analysis.name = Some(name.clone());
-  track_analysis._type = track_analysis::activity_type_from_string(&activity);
-  io::write_track_analysis(&track_analysis).unwrap();
-  // gpx writing takes longer and is therefore handled in separate thread
+  let lock = locks.get(&ulid);
+  // gpx writing takes longer and is therefore handled in separate thread,
+  // which holds the lock of the track until analysis and gpx are written
   std::thread::spawn(move || {
-    println!("{} : {} : {}", ulid, name, activity);
-    let mut gpx = io::read_gpx(&ulid).unwrap();
-    gpx.tracks[0].name = Some(name);
-    io::write_gpx(&gpx, &ulid);
+    let _guard = lock::acquire(&lock);
+    match history::edit(&ulid, Operation::Edit, || write_track_changes(&ulid, &name, &activity)) {
+      Ok(()) => { window.emit("track_changed", &ulid).ok(); },
+      Err(e) => println!("{:?}", e),
+    }
   });
 }
 
+/// Name and activity are written to the analysis and the gpx together
+fn write_track_changes(ulid: &String, name: &str, activity: &str) -> Result<(), MaplineError> {
+  let write_error = |e: std::io::Error| MaplineError::WriteError(e.to_string());
+  let mut track_analysis = io::read_track_analysis(ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+  let mut gpx = io::read_gpx(ulid).ok_or_else(|| MaplineError::FileError("record.gpx could not be read".to_string()))?;
+  track_analysis.name = Some(name.to_string());
+  track_analysis._type = track_analysis::activity_type_from_string(&activity.to_string());
+  if let Some(track) = gpx.tracks.first_mut() {
+    track.name = Some(name.to_string());
+  }
+  io::begin_write(ulid).map_err(write_error)?;
+  io::write_track_analysis(&track_analysis).map_err(write_error)?;
+  io::write_gpx(&gpx, ulid).map_err(write_error)?;
+  io::end_write(ulid).map_err(write_error)
+}
+
+/// Moves the track to the trash, it is purged after the days set in the settings
+#[tauri::command]
+fn delete_track(locks: tauri::State<TrackLocks>, ulid: String) {
+  if let Err(e) = locks.with(&[ulid.clone()], || trash::trash(&ulid)) {
+    println!("{:?}", e);
+  }
+}
+
+#[tauri::command]
+fn load_trash() -> Vec<TrackAnalysis> {
+  index::trash().unwrap_or_else(|e| { println!("{:?}", e); vec![] })
+}
+
+#[tauri::command]
+fn restore_track(locks: tauri::State<TrackLocks>, ulid: String) -> Option<TrackAnalysis> {
+  match locks.with(&[ulid.clone()], || trash::restore(&ulid)) {
+    Ok(ta) => Some(ta),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+/// Deletes a trashed track for good
+#[tauri::command]
+fn purge_track(locks: tauri::State<TrackLocks>, ulid: String) {
+  if let Err(e) = locks.with(&[ulid.clone()], || trash::purge(&ulid)) {
+    println!("{:?}", e);
+  }
+}
+
+/// Archived tracks are hidden from the list, but still found by the filter
 #[tauri::command]
-fn delete_track(ulid: String) {
-  // println!("remove {:?}", paths::track(ulid));
-  std::fs::remove_dir_all(paths::track(&ulid));
+fn set_archived(locks: tauri::State<TrackLocks>, ulid: String, archived: bool) -> Option<TrackAnalysis> {
+  match locks.with(&[ulid.clone()], || trash::set_archived(&ulid, archived)) {
+    Ok(ta) => Some(ta),
+    Err(e) => { println!("{:?}", e); None },
+  }
 }
 
+/// The new track, or the reason the join failed (the parts are unchanged then)
 #[tauri::command]
-fn join_tracks(ulids: Vec<String>) {
-  util::join_tracks(ulids);
+fn join_tracks(locks: tauri::State<TrackLocks>, ulids: Vec<String>) -> Result<TrackAnalysis, String> {
+  let parts = ulids.clone();
+  locks.with(&parts, || util::join_tracks(ulids)).map_err(|e| {
+    println!("{:?}", e);
+    format!("{:?}", e)
+  })
+}
+
+#[tauri::command]
+fn recalculate_track(locks: tauri::State<TrackLocks>, ulid: String) {
+  let result = locks.with(&[ulid.clone()], || {
+    history::edit(&ulid, Operation::Recalculate, || util::recalculate_track(ulid.clone()))
+  });
+  if let Err(e) = result {
+    println!("{:?}", e);
+  }
 }
 
+/// Recalculates the elevation profile and stats with "barometric", "gps" or "dem"
 #[tauri::command]
-fn recalculate_track(ulid: String) {
-  util::recalculate_track(ulid);
-}
\ No newline at end of file
+fn set_elevation_source(locks: tauri::State<TrackLocks>, ulid: String, source: String) -> Option<TrackAnalysis> {
+  let source = elevation::elevation_source_from_string(&source)?;
+  match locks.with(&[ulid.clone()], || history::edit(&ulid, Operation::ElevationSource, || util::set_elevation_source(&ulid, source))) {
+    Ok(ta) => Some(ta),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+/// Elevation profiles of the device and the elevation model, for comparison
+#[tauri::command]
+fn load_elevation_profiles(ulid: String) -> Option<ElevationProfiles> {
+  util::elevation_profiles(&ulid)
+}
+
+/// Rebuilds the track from the original file kept in the track folder
+#[tauri::command]
+fn reimport_track(locks: tauri::State<TrackLocks>, ulid: String) -> Option<TrackAnalysis> {
+  match locks.with(&[ulid.clone()], || history::edit(&ulid, Operation::Reimport, || import::reimport(&ulid))) {
+    Ok(ta) => Some(ta),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+/// Repairs track folders left inconsistent by an interrupted write, also run on startup
+#[tauri::command]
+fn check_library(locks: tauri::State<TrackLocks>) -> RepairReport {
+  repair::check_library(&locks)
+}
+
+/// Operations of the track, the latest last
+#[tauri::command]
+fn load_history(ulid: String) -> Option<Journal> {
+  history::journal(&ulid).ok()
+}
+
+/// Reverts the latest operation of the track, undoing a join restores its parts
+#[tauri::command]
+fn undo_track(locks: tauri::State<TrackLocks>, ulid: String) -> Option<Journal> {
+  match locks.with(&history::related(&ulid), || history::undo(&ulid)) {
+    Ok(journal) => Some(journal),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+#[tauri::command]
+fn redo_track(locks: tauri::State<TrackLocks>, ulid: String) -> Option<Journal> {
+  match locks.with(&history::related(&ulid), || history::redo(&ulid)) {
+    Ok(journal) => Some(journal),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+/// Moves the open library, returns whether the app has to be restarted for it
+fn move_open_library(target: &PathBuf) -> Option<bool> {
+  let info = load_libraries()?;
+  let name = match info.name {
+    Some(n) => n,
+    None => { println!("{} is not a named library", info.path.to_string_lossy()); return None },
+  };
+  match library::relocate(&name, target) {
+    Ok(restart) => Some(restart),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+#[tauri::command]
+fn load_libraries() -> Option<LibraryInfo> {
+  match library::info() {
+    Ok(info) => Some(info),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+/// Adds a library at the folder, an existing library in it is kept
+#[tauri::command]
+fn add_library(name: String, path: String) -> Option<LibraryInfo> {
+  match library::add(&name, &PathBuf::from(path)) {
+    Ok(_) => load_libraries(),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
+
+/// Opens the library, the app is restarted
+#[tauri::command]
+fn switch_library(app: tauri::AppHandle, name: String) {
+  match library::switch(&name) {
+    Ok(()) => tauri::api::process::restart(&app.env()),
+    Err(e) => println!("{:?}", e),
+  }
+}
+
+/// Moves the library to an empty folder, the open library is moved on a restart
+#[tauri::command]
+fn move_library(app: tauri::AppHandle, name: String, path: String) -> Option<LibraryInfo> {
+  match library::relocate(&name, &PathBuf::from(path)) {
+    Ok(true) => { tauri::api::process::restart(&app.env()); None },
+    Ok(false) => load_libraries(),
+    Err(e) => { println!("{:?}", e); None },
+  }
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+
+use crate::{elevation, io, paths};
+use crate::errors::MaplineError;
+use crate::line::arrange_display;
+use crate::sensor::SensorData;
+use crate::track_analysis::{self, TrackAnalysis};
+use crate::track_note::{TrackNote, TrackNotes};
+
+/// Upgrades the json of a file from one version to the next
+type Step = fn(&mut Value);
+
+/// Step i upgrades version i + 1 to i + 2
+const ANALYSIS_STEPS: [Step; 1] = [analysis_v2];
+const SETTINGS_STEPS: [Step; 1] = [settings_v2];
+const NOTES_STEPS: [Step; 1] = [notes_v2];
+
+/// Reads a versioned json file. Files of an older version are upgraded step by
+/// step in memory, the version they were upgraded from is returned. Callers
+/// writing the upgraded file back keep the original as backup next to it (e.g.
+/// analysis.json.v1.bak).
+fn read<T: DeserializeOwned>(path: &Path, steps: &[Step], current: i32) -> Result<(T, Option<i32>), MaplineError> {
+    let json_string = fs::read_to_string(path).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    let mut value: Value = serde_json::from_str(&json_string).map_err(|e| MaplineError::ParseError(e.to_string()))?;
+    let version = version_of(&value);
+    let upgraded = version < current;
+    if upgraded {
+        for step in steps.iter().skip((version - 1).max(0) as usize) {
+            step(&mut value);
+        }
+        if let Value::Object(o) = &mut value {
+            o.insert("version".to_string(), json!(current));
+        }
+    }
+    // files of a newer version are read as far as possible, but never written
+    let result = serde_json::from_value(value).map_err(|e| MaplineError::ParseError(e.to_string()))?;
+    Ok((result, if upgraded { Some(version) } else { None }))
+}
+
+/// Files written before versioning (e.g. notes as plain list) are version 1
+fn version_of(value: &Value) -> i32 {
+    value.get("version").and_then(|v| v.as_i64()).unwrap_or(1) as i32
+}
+
+fn backup(path: &Path, version: i32) -> Result<PathBuf, MaplineError> {
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(format!(".v{}.bak", version));
+    let backup_path = PathBuf::from(backup_path);
+    // an existing backup is the oldest original, it is not overwritten
+    if !backup_path.exists() {
+        fs::copy(path, &backup_path).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    }
+    Ok(backup_path)
+}
+
+/// Reads the analysis of the track upgraded to the current version. Nothing is
+/// written, the upgraded file is persisted by upgrade_track_analysis.
+pub fn track_analysis(ulid: &str) -> Result<TrackAnalysis, MaplineError> {
+    read::<TrackAnalysis>(&paths::track_analysis(ulid), &ANALYSIS_STEPS, crate::ANALYSIS_VERSION).map(|(ta, _)| ta)
+}
+
+/// Writes the analysis of the track back in the current version. If it can not
+/// be upgraded, it is recomputed from record.gpx. Analyses of a newer version are
+/// never recomputed, that would overwrite them. Only called by the library check
+/// at startup, under the lock of the track. True if the analysis was written.
+pub fn upgrade_track_analysis(ulid: &str) -> Result<bool, MaplineError> {
+    let path = paths::track_analysis(ulid);
+    match read::<TrackAnalysis>(&path, &ANALYSIS_STEPS, crate::ANALYSIS_VERSION) {
+        Ok((_, None)) => Ok(false),
+        Ok((ta, Some(version))) => {
+            backup(&path, version)?;
+            io::write_track_analysis_file(&ta).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+            Ok(true)
+        }
+        Err(MaplineError::ParseError(e)) if paths::track_gpx(ulid).exists() && !newer(&path) => {
+            println!("Analysis of {} can not be read ({}), recomputing it", ulid, e);
+            recompute(ulid).map(|_| true)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// True if the file is of a newer version than this build writes. Files that are
+/// no json at all are broken, not newer.
+fn newer(path: &Path) -> bool {
+    fs::read_to_string(path).ok()
+        .and_then(|s| serde_json::from_str::<Value>(&s).ok())
+        .map(|v| version_of(&v) > crate::ANALYSIS_VERSION)
+        .unwrap_or(false)
+}
+
+/// Field of the old analysis, None if it is missing or can not be read
+fn old_field<T: DeserializeOwned>(old: &Value, key: &str) -> Option<T> {
+    old.get(key).and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+
+/// New analysis from record.gpx. Every field set by the user or the import
+/// (e.g. name, tags, device summary, linked route) is kept from the old file if
+/// it can still be read.
+pub fn recompute(ulid: &str) -> Result<TrackAnalysis, MaplineError> {
+    let path = paths::track_analysis(ulid);
+    let old: Value = fs::read_to_string(&path).ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or(Value::Null);
+    if path.exists() {
+        backup(&path, version_of(&old))?;
+    }
+    let gpx = io::read_gpx(&ulid.to_string()).ok_or_else(|| MaplineError::FileError("record.gpx could not be read".to_string()))?;
+    let sensor_data: SensorData = io::read_sensor_data(ulid).unwrap_or_default();
+    let activity = old.get("_type").and_then(|t| t.as_str()).map(|t| track_analysis::activity_type_from_string(&t.to_string()));
+    let flight = matches!(activity, Some(track_analysis::Activity::Flying));
+    let source = old_field(&old, "elevation_source")
+        .unwrap_or_else(|| elevation::default_source(&gpx, &sensor_data, flight));
+
+    let geojson = arrange_display(&gpx, None, None);
+    let (mut ta, ele, coords) = TrackAnalysis::analyze(Some(ulid.to_string()), &geojson, &gpx, activity, source, &sensor_data);
+    ta.name = old_field(&old, "name").or(ta.name);
+    ta.tags = old_field(&old, "tags").unwrap_or(ta.tags);
+    ta.archived = old_field(&old, "archived").unwrap_or(false);
+    ta.trashed = old_field(&old, "trashed");
+    ta.device_summary = old_field(&old, "device_summary").or(ta.device_summary);
+    ta.summary_source = old_field(&old, "summary_source").unwrap_or(ta.summary_source);
+    ta.multisport = old_field(&old, "multisport").or(ta.multisport);
+    ta.flight = old_field(&old, "flight").or(ta.flight);
+    ta.planned_route = old_field(&old, "planned_route").or(ta.planned_route);
+    let geojson = arrange_display(&gpx, Some(geojson), Some(&ta.pauses));
+    let write_error = |e: std::io::Error| MaplineError::WriteError(e.to_string());
+    io::begin_write(ulid).map_err(write_error)?;
+    io::write_elevation(ele, coords, ulid).map_err(write_error)?;
+    io::write_geojson(&geojson, ulid).map_err(write_error)?;
+    io::write_track_analysis_file(&ta).map_err(write_error)?;
+    io::end_write(ulid).map_err(write_error)?;
+    Ok(ta)
+}
+
+/// Settings and whether they were upgraded, the caller writes them back
+pub fn settings<T: DeserializeOwned>(path: &Path) -> Result<(T, bool), MaplineError> {
+    let (settings, upgraded_from) = read(path, &SETTINGS_STEPS, crate::SETTINGS_VERSION)?;
+    if let Some(version) = upgraded_from {
+        backup(path, version)?;
+    }
+    Ok((settings, upgraded_from.is_some()))
+}
+
+/// Notes of the track, upgraded notes are written back
+pub fn track_notes(ulid: &str) -> Result<Vec<TrackNote>, MaplineError> {
+    let path = paths::track_notes(ulid);
+    let (file, upgraded_from): (TrackNotes, Option<i32>) = read(&path, &NOTES_STEPS, crate::NOTES_VERSION)?;
+    if let Some(version) = upgraded_from {
+        backup(&path, version)?;
+        io::write_track_notes_file(&file, ulid).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    }
+    Ok(file.notes)
+}
+
+/// Fields added after version 1, older files only set some of them
+fn analysis_v2(value: &mut Value) {
+    if let Value::Object(o) = value {
+        for key in ["start_time", "end_time", "time_moving", "time_total", "avg_vel", "device_summary", "multisport", "flight", "planned_route", "elevation_source"] {
+            o.entry(key).or_insert(Value::Null);
+        }
+        o.entry("summary_source").or_insert(json!("Computed"));
+        o.entry("pauses").or_insert(json!([]));
+        o.entry("tags").or_insert(json!([]));
+    }
+}
+
+fn settings_v2(value: &mut Value) {
+    if let Value::Object(o) = value {
+        o.entry("import_paths").or_insert(json!([]));
+        o.entry("csv_columns").or_insert(json!({}));
+    }
+}
+
+/// Notes were a plain list, they are now stored with a version
+fn notes_v2(value: &mut Value) {
+    if value.is_array() {
+        let mut o = Map::new();
+        o.insert("notes".to_string(), value.take());
+        *value = Value::Object(o);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("maplines-migrate-{}-{}.json", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn files_without_version_are_version_1() {
+        assert_eq!(version_of(&json!([])), 1);
+        assert_eq!(version_of(&json!({"name": "a"})), 1);
+        assert_eq!(version_of(&json!({"version": 2})), 2);
+    }
+
+    #[test]
+    fn notes_list_is_wrapped() {
+        let mut value = json!([{"id": "a"}]);
+        notes_v2(&mut value);
+        assert_eq!(value, json!({"notes": [{"id": "a"}]}));
+    }
+
+    #[test]
+    fn analysis_v2_keeps_existing_fields() {
+        let mut value = json!({"tags": ["alps"], "start_time": "2022-08-12T10:00:00Z"});
+        analysis_v2(&mut value);
+        assert_eq!(value["tags"], json!(["alps"]));
+        assert_eq!(value["start_time"], json!("2022-08-12T10:00:00Z"));
+        assert_eq!(value["pauses"], json!([]));
+        assert_eq!(value["summary_source"], json!("Computed"));
+        assert_eq!(value["device_summary"], Value::Null);
+    }
+
+    #[test]
+    fn settings_v2_adds_import_paths() {
+        let mut value = json!({"trash_days": 30});
+        settings_v2(&mut value);
+        assert_eq!(value["import_paths"], json!([]));
+        assert_eq!(value["csv_columns"], json!({}));
+    }
+
+    #[test]
+    fn old_file_is_upgraded_in_memory_only() {
+        let path = temp_file("old", "[]");
+        let (value, upgraded_from): (TrackNotes, Option<i32>) = read(&path, &NOTES_STEPS, 2).unwrap();
+        assert_eq!(value.version, 2);
+        assert!(value.notes.is_empty());
+        assert_eq!(upgraded_from, Some(1));
+        // neither written back nor backed up, see upgrade_track_analysis
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[]");
+        let mut backup_path = path.as_os_str().to_owned();
+        backup_path.push(".v1.bak");
+        assert!(!PathBuf::from(backup_path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn current_and_newer_files_are_not_upgraded() {
+        let path = temp_file("newer", r#"{"version": 3, "notes": [], "added_later": true}"#);
+        let (value, upgraded_from): (TrackNotes, Option<i32>) = read(&path, &NOTES_STEPS, 2).unwrap();
+        assert_eq!(value.version, 3);
+        assert_eq!(upgraded_from, None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn broken_json_is_a_parse_error() {
+        let path = temp_file("broken", "{\"version\": 2, ");
+        let result: Result<(TrackNotes, Option<i32>), MaplineError> = read(&path, &NOTES_STEPS, 2);
+        assert!(matches!(result, Err(MaplineError::ParseError(_))));
+        fs::remove_file(&path).unwrap();
+    }
+}
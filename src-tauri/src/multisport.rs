@@ -0,0 +1,108 @@
+use serde::{Serialize, Deserialize};
+use ulid::Ulid;
+
+use crate::summary::DeviceSummary;
+use crate::track_analysis::{Activity, TrackAnalysis};
+
+/// Set by the importer on every leg of a multisport file
+#[derive(Clone, Debug, Default)]
+pub struct LegInfo {
+    pub position: usize, // of the leg in the event
+    pub transition_sec: u64, // time until the next leg starts
+    pub untracked: Vec<UntrackedLeg>, // legs of the event without track, the same for every leg
+}
+
+/// Leg without gps points (e.g. pool swimming or an indoor trainer), only the
+/// totals of the device are known
+#[derive(Clone, Debug)]
+pub struct UntrackedLeg {
+    pub position: usize,
+    pub activity: Activity,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub summary: DeviceSummary,
+    pub transition_sec: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MultisportLeg {
+    pub ulid: Option<String>, // None for legs without track
+    pub activity: Activity,
+    pub distance: f64, // in meters
+    pub time_total: Option<u64>,
+    pub transition_sec: u64, // transition after this leg
+}
+
+/// Legs of one multisport event (e.g. triathlon) with the combined summary
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MultisportEvent {
+    pub id: String,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub legs: Vec<MultisportLeg>,
+    pub distance: f64, // in meters
+    pub time_total: u64, // including transitions
+    pub time_transitions: u64,
+}
+
+impl MultisportEvent {
+    /// Event of the tracked legs and the legs without track, in their order
+    pub fn new(analysis: &Vec<(TrackAnalysis, LegInfo)>) -> MultisportEvent {
+        // position, start and end time of every leg
+        let mut ordered: Vec<(usize, Option<String>, Option<String>, MultisportLeg)> = analysis.iter().map(|(ta, info)| {
+            (info.position, ta.start_time.clone(), ta.end_time.clone(), MultisportLeg {
+                ulid: Some(ta.ulid.clone()),
+                activity: ta._type.clone(),
+                distance: ta.distance,
+                time_total: ta.time_total,
+                transition_sec: info.transition_sec,
+            })
+        }).collect();
+        if let Some((_, info)) = analysis.first() {
+            ordered.extend(info.untracked.iter().map(|u| {
+                (u.position, u.start_time.clone(), u.end_time.clone(), MultisportLeg {
+                    ulid: None,
+                    activity: u.activity.clone(),
+                    distance: u.summary.distance.unwrap_or(0.),
+                    time_total: u.summary.time_total,
+                    transition_sec: u.transition_sec,
+                })
+            }));
+        }
+        ordered.sort_by_key(|l| l.0);
+        let start_time = ordered.first().and_then(|l| l.1.clone());
+        let end_time = ordered.last().and_then(|l| l.2.clone());
+        let legs: Vec<MultisportLeg> = ordered.into_iter().map(|l| l.3).collect();
+        let time_transitions = legs.iter().map(|l| l.transition_sec).sum::<u64>();
+        MultisportEvent {
+            id: Ulid::new().to_string(),
+            start_time,
+            end_time,
+            distance: legs.iter().map(|l| l.distance).sum(),
+            time_total: legs.iter().map(|l| l.time_total.unwrap_or(0)).sum::<u64>() + time_transitions,
+            time_transitions,
+            legs,
+        }
+    }
+
+    /// Takes the summary of a leg from its new analysis (e.g. after a reimport)
+    /// and sums up the event again
+    pub fn update_leg(&mut self, ta: &TrackAnalysis) {
+        let position = match self.legs.iter().position(|l| l.ulid.as_deref() == Some(ta.ulid.as_str())) {
+            Some(p) => p,
+            None => return,
+        };
+        let leg = &mut self.legs[position];
+        leg.activity = ta._type.clone();
+        leg.distance = ta.distance;
+        leg.time_total = ta.time_total;
+        if position == 0 {
+            self.start_time = ta.start_time.clone();
+        }
+        if position == self.legs.len() - 1 {
+            self.end_time = ta.end_time.clone();
+        }
+        self.distance = self.legs.iter().map(|l| l.distance).sum();
+        self.time_total = self.legs.iter().map(|l| l.time_total.unwrap_or(0)).sum::<u64>() + self.time_transitions;
+    }
+}
@@ -1,12 +1,41 @@
 use std::path::PathBuf;
 use std::fs;
 
+use once_cell::sync::OnceCell;
+
+/// Root of the open library, set once on startup (see library::open)
+static LIBRARY_ROOT: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn set_maplines(root: PathBuf) {
+    if LIBRARY_ROOT.set(root).is_err() {
+        println!("Library root is already set");
+    }
+}
+
 pub fn maplines() -> PathBuf {
-    let mut data_path = dirs::data_dir().unwrap();
+    LIBRARY_ROOT.get().cloned().unwrap_or_else(default_library)
+}
+
+/// Library of installations without configured libraries. Systems without
+/// data directory fall back to the home or the working directory.
+pub fn default_library() -> PathBuf {
+    let mut data_path = dirs::data_dir()
+        .or_else(dirs::home_dir)
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
     data_path.push("Maplines");
     data_path
 }
 
+/// Names and locations of all libraries. Kept outside of the libraries, so a
+/// library can be moved without it.
+pub fn libraries() -> PathBuf {
+    let mut config_path = dirs::config_dir()
+        .or_else(dirs::home_dir)
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+    config_path.push("maplines_libraries.json");
+    config_path
+}
+
 pub fn tracks() -> PathBuf {
     let mut tracks_path = maplines();
     tracks_path.push("tracks");
@@ -50,6 +79,130 @@ pub fn track_notes(ulid: &str) -> PathBuf {
 
 }
 
+pub fn track_sensors(ulid: &str) -> PathBuf {
+    let mut sensors_path = track(ulid);
+    sensors_path.push("sensors.json");
+    sensors_path
+}
+
+pub fn track_laps(ulid: &str) -> PathBuf {
+    let mut laps_path = track(ulid);
+    laps_path.push("laps.json");
+    laps_path
+}
+
+pub fn track_source(ulid: &str) -> PathBuf {
+    let mut source_path = track(ulid);
+    source_path.push("source.json");
+    source_path
+}
+
+/// Exists while the files of the track are written, see repair
+pub fn track_writing(ulid: &str) -> PathBuf {
+    let mut writing_path = track(ulid);
+    writing_path.push("writing");
+    writing_path
+}
+
+/// Edit journal and the saved states of the track
+pub fn track_history(ulid: &str) -> PathBuf {
+    let mut history_path = track(ulid);
+    history_path.push("history");
+    history_path
+}
+
+pub fn track_journal(ulid: &str) -> PathBuf {
+    let mut journal_path = track_history(ulid);
+    journal_path.push("journal.json");
+    journal_path
+}
+
+/// Copy of the imported file, e.g. original.fit
+pub fn track_original(ulid: &str, format: &str) -> PathBuf {
+    let mut original_path = track(ulid);
+    original_path.push("original");
+    original_path.set_extension(format);
+    original_path
+}
+
+pub fn multisport_events() -> PathBuf {
+    let mut events_path = maplines();
+    events_path.push("multisport");
+    events_path
+}
+
+pub fn multisport_event(id: &str) -> PathBuf {
+    let mut event_path = multisport_events();
+    event_path.push(id);
+    event_path.set_extension("json");
+    event_path
+}
+
+/// Planned routes, stored like the tracks in one folder per route
+pub fn routes() -> PathBuf {
+    let mut routes_path = maplines();
+    routes_path.push("routes");
+    routes_path
+}
+
+pub fn route(ulid: &str) -> PathBuf {
+    let mut route_path = routes();
+    route_path.push(ulid);
+    route_path
+}
+
+pub fn route_analysis(ulid: &str) -> PathBuf {
+    let mut analysis_path = route(ulid);
+    analysis_path.push("route.json");
+    analysis_path
+}
+
+pub fn route_gpx(ulid: &str) -> PathBuf {
+    let mut gpx_path = route(ulid);
+    gpx_path.push("route.gpx");
+    gpx_path
+}
+
+pub fn route_geojson(ulid: &str) -> PathBuf {
+    let mut geojson_path = route(ulid);
+    geojson_path.push("geometries.geojson");
+    geojson_path
+}
+
+pub fn route_elevation(ulid: &str) -> PathBuf {
+    let mut elevation_path = route(ulid);
+    elevation_path.push("elevation.json");
+    elevation_path
+}
+
+/// Points of interest of all imported waypoints
+pub fn pois() -> PathBuf {
+    let mut pois_path = maplines();
+    pois_path.push("pois.json");
+    pois_path
+}
+
+/// Sqlite index of the track folders
+pub fn index() -> PathBuf {
+    let mut index_path = maplines();
+    index_path.push("index.sqlite");
+    index_path
+}
+
+/// Record of the files already seen in the import paths
+pub fn seen_files() -> PathBuf {
+    let mut seen_path = maplines();
+    seen_path.push("seen_files.json");
+    seen_path
+}
+
+/// Track folders that could not be repaired on startup
+pub fn broken() -> PathBuf {
+    let mut broken_path = maplines();
+    broken_path.push("broken");
+    broken_path
+}
+
 // Shuttle Radar Topographic Mission
 pub fn srtm() -> PathBuf {
     let mut srtm_path = maplines();
@@ -60,4 +213,6 @@ pub fn srtm() -> PathBuf {
 pub fn create_dirs_if_not_exist() {
     fs::create_dir_all(tracks()).unwrap();
     fs::create_dir_all(srtm()).unwrap();
+    fs::create_dir_all(multisport_events()).unwrap();
+    fs::create_dir_all(routes()).unwrap();
 }
\ No newline at end of file
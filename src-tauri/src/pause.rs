@@ -4,6 +4,8 @@ use gpx::{Gpx, Waypoint};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
+use crate::segment;
+
 /// Scattered points within this radius can be declared as clusters -> Pauses
 const SCATTER_RADIUS: f64 = 40.0;
 /// Consecutive points must lie within a SCATTER_RADIUS for at least MIN_CLUSTER_TIME to be considered a cluster
@@ -35,8 +37,64 @@ pub fn find(track_analysis: TrackAnalysis) -> Option<Vec<Pause>> {
 }
 */
 
+/// Finds the pauses of every segment. The gap between two segments
+/// is counted as a pause as well.
 pub fn find(gpx: &Gpx) -> Vec<Pause> {
-    return find_clusters(gpx)
+    let mut result: Vec<Pause> = vec![];
+    let mut offset = 0;
+    let mut last_point: Option<&Waypoint> = None;
+    for s in gpx.tracks.iter().flat_map(|t| t.segments.iter()) {
+        if s.points.is_empty() {
+            continue;
+        }
+        if let Some(l) = last_point {
+            result.push(segment_gap(l, offset - 1, &s.points[0], offset));
+        }
+        // clusters can only be detected on timestamped points
+        if s.points.iter().all(|p| p.time.is_some()) {
+            for mut p in find_clusters(&s.points) {
+                p.index_before += offset;
+                p.index_after += offset;
+                result.push(p);
+            }
+        }
+        offset += s.points.len();
+        last_point = s.points.last();
+    }
+    merge_overlapping(result, &segment::points(gpx))
+}
+
+/// Pause between the last point of a segment and the first point of the next one
+fn segment_gap(before: &Waypoint, index_before: usize, after: &Waypoint, index_after: usize) -> Pause {
+    let duration = match (before.time, after.time) {
+        (Some(b), Some(a)) => (OffsetDateTime::from(a).unix_timestamp() - OffsetDateTime::from(b).unix_timestamp()).max(0) as u64,
+        _ => 0,
+    };
+    Pause::new(before.point().into(), index_before, after.point().into(), index_after, duration)
+}
+
+/// Clusters touching a segment gap are combined with the gap into one pause.
+/// The duration of a merged pause is the time between its first and last point,
+/// points are all points of the gpx (the indices of the pauses refer to them).
+fn merge_overlapping(pauses: Vec<Pause>, points: &[&Waypoint]) -> Vec<Pause> {
+    let mut result: Vec<Pause> = vec![];
+    for p in pauses {
+        match result.last_mut() {
+            Some(l) if p.index_before <= l.index_after => {
+                if p.index_after > l.index_after {
+                    l.index_after = p.index_after;
+                    l.coord_after = p.coord_after;
+                }
+                let time = |i: usize| points.get(i).and_then(|w| w.time).map(|t| OffsetDateTime::from(t).unix_timestamp());
+                l.duration_sec = match (time(l.index_before), time(l.index_after)) {
+                    (Some(b), Some(a)) => (a - b).max(0) as u64,
+                    _ => l.duration_sec.max(p.duration_sec),
+                };
+            }
+            _ => result.push(p),
+        }
+    }
+    result
 }
 
 // TODO: Error handling
@@ -68,7 +126,7 @@ fn find_time_jumps(gpx: &Gpx, min_break_time: u64) -> Result<Option<Vec<Pause>>,
 */
 
 /// Find consecutive gps points building 'clusters' within a constrained area
-fn find_clusters(gpx: &Gpx) -> Vec<Pause> {
+fn find_clusters(points: &[Waypoint]) -> Vec<Pause> {
     let mut result: Vec<Pause> = vec![];
     let mut detection_completed = false;
     let mut pos = 0;
@@ -79,15 +137,15 @@ fn find_clusters(gpx: &Gpx) -> Vec<Pause> {
     while !detection_completed {
 
         detection_completed = true;
-        let start_point = gpx.tracks[0].segments[0].points[pos].point();
-        let start_time = OffsetDateTime::from(gpx.tracks[0].segments[0].points[pos].time.unwrap()).unix_timestamp();
+        let start_point = points[pos].point();
+        let start_time = OffsetDateTime::from(points[pos].time.unwrap()).unix_timestamp();
         let mut current_cluster: Vec<&Waypoint> = vec![];
-        for q in gpx.tracks[0].segments[0].points[pos..].iter() {
-            if q == gpx.tracks[0].segments[0].points.last().unwrap() {
+        for q in points[pos..].iter() {
+            if q == points.last().unwrap() {
                 current_cluster.push(q);
             } else if start_point.haversine_distance(&q.point().into()) < SCATTER_RADIUS || current_cluster.len() < 2 {
                 current_cluster.push(q);
-                if q == gpx.tracks[0].segments[0].points.last().unwrap() {
+                if q == points.last().unwrap() {
                     println!("clust is not yet added. length: {}", current_cluster.len());
                 }
                 continue;
@@ -101,7 +159,7 @@ fn find_clusters(gpx: &Gpx) -> Vec<Pause> {
                 cluster = current_cluster;
                 cluster_index = pos;
                 pos += index; // This loses time spent until index (CHECK THIS!!!)
-                if q != gpx.tracks[0].segments[0].points.last().unwrap() {
+                if q != points.last().unwrap() {
                     detection_completed = false;
                 }
                 break;
@@ -120,7 +178,7 @@ fn find_clusters(gpx: &Gpx) -> Vec<Pause> {
                 }
                 pos += current_cluster.len();
                 time_in_radius = 0;
-                if q != gpx.tracks[0].segments[0].points.last().unwrap() {
+                if q != points.last().unwrap() {
                     detection_completed = false;
                 }
                 cluster = vec![];
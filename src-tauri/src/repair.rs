@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{import, index, io, migrate, paths, util};
+use crate::errors::MaplineError;
+use crate::lock::TrackLocks;
+
+/// Result of the library check
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RepairReport {
+    pub checked: usize,
+    pub migrated: Vec<String>, // analyses written back in the current version
+    pub temp_files: usize, // left over by interrupted writes, removed
+    pub repaired: Vec<String>,
+    pub failed: Vec<(String, String)>, // ulid and error, the folder is left as it is
+    pub broken: Vec<(String, String)>, // ulid and error, moved to paths::broken()
+    pub removed: Vec<String>, // first imports interrupted before the analysis was written
+}
+
+impl RepairReport {
+    pub fn is_clean(&self) -> bool {
+        self.temp_files == 0 && self.repaired.is_empty() && self.failed.is_empty() && self.broken.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Finds track folders left inconsistent by an interrupted write and rebuilds
+/// them from record.gpx, or from the original file if record.gpx can not be
+/// read. Folders without either are moved out of the library. Analyses of an
+/// older version are written back upgraded, reads only upgrade them in memory.
+pub fn check_library(locks: &TrackLocks) -> RepairReport {
+    let mut report = RepairReport::default();
+    report.temp_files += remove_temp_files(&paths::maplines());
+    if let Ok(dirs) = fs::read_dir(paths::routes()) {
+        for d in dirs.filter_map(|d| d.ok()) {
+            report.temp_files += remove_temp_files(&d.path());
+        }
+    }
+    for ulid in index::track_ulids() {
+        report.checked += 1;
+        locks.with(&[ulid.clone()], || check_track(&ulid, &mut report));
+    }
+    report
+}
+
+fn check_track(ulid: &String, report: &mut RepairReport) {
+    report.temp_files += remove_temp_files(&paths::track(ulid));
+    report.temp_files += remove_temp_files_in_tree(&paths::track_history(ulid));
+    if !needs_repair(ulid) {
+        match migrate::upgrade_track_analysis(ulid) {
+            Ok(true) => report.migrated.push(ulid.clone()),
+            Ok(false) => {}
+            Err(e) => report.failed.push((ulid.clone(), format!("{:?}", e))),
+        }
+        return;
+    }
+    // a first import interrupted before the analysis, nothing of the user is in
+    // the folder yet and the file is imported again with its folder
+    if paths::track_writing(ulid).exists() && !paths::track_analysis(ulid).exists() && !paths::track_gpx(ulid).exists() {
+        println!("Removing interrupted import {}", ulid);
+        match fs::remove_dir_all(paths::track(ulid)) {
+            Ok(()) => {
+                if let Err(e) = index::remove(ulid) {
+                    println!("{:?}", e);
+                }
+                report.removed.push(ulid.clone());
+            }
+            Err(e) => report.failed.push((ulid.clone(), e.to_string())),
+        }
+        return;
+    }
+    println!("Repairing track {}", ulid);
+    if io::read_gpx(ulid).is_none() {
+        // the original file is the only other copy of the geometry
+        let result = match paths::track_source(ulid).exists() {
+            true => import::reimport(ulid).map(|_| ()),
+            false => Err(MaplineError::FileError("record.gpx can not be read and there is no original file".to_string())),
+        };
+        match result {
+            Ok(()) => report.repaired.push(ulid.clone()),
+            Err(e) => {
+                if let Err(e) = move_to_broken(ulid) {
+                    println!("Could not move track {}: {:?}", ulid, e);
+                }
+                report.broken.push((ulid.clone(), format!("{:?}", e)));
+            }
+        }
+        return;
+    }
+    match rebuild(ulid) {
+        Ok(()) => report.repaired.push(ulid.clone()),
+        Err(e) => report.failed.push((ulid.clone(), format!("{:?}", e))),
+    }
+}
+
+/// Folders of interrupted writes and folders missing a file every track has.
+/// Tracks joined by older versions have no elevation.json, so it is not required.
+fn needs_repair(ulid: &str) -> bool {
+    paths::track_writing(ulid).exists()
+        || !paths::track_analysis(ulid).exists()
+        || !paths::track_gpx(ulid).exists()
+        || !paths::track_geojson(ulid).exists()
+}
+
+/// Everything but record.gpx, the notes and the original file is derived, so
+/// it is computed again. The changes of the user are kept.
+fn rebuild(ulid: &String) -> Result<(), MaplineError> {
+    if !paths::track_analysis(ulid).exists() {
+        migrate::recompute(ulid)?;
+    } else {
+        migrate::upgrade_track_analysis(ulid)?;
+    }
+    let ta = util::recalculate_track(ulid.clone())?;
+    if let Some(source) = ta.elevation_source {
+        util::set_elevation_source(ulid, source)?;
+    }
+    Ok(())
+}
+
+fn move_to_broken(ulid: &str) -> Result<(), MaplineError> {
+    let mut target = paths::broken();
+    fs::create_dir_all(&target).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    target.push(ulid);
+    fs::rename(paths::track(ulid), target).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    index::remove(ulid)
+}
+
+/// Removes the temp files of io::write_atomic in the folder, returns their number
+fn remove_temp_files(dir: &Path) -> usize {
+    let entries = match fs::read_dir(dir) {
+        Err(_) => return 0,
+        Ok(e) => e,
+    };
+    entries.filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && io::is_temp_file(p))
+        .filter(|p| fs::remove_file(p).is_ok())
+        .count()
+}
+
+/// Like remove_temp_files, including all folders below dir (e.g. the saved
+/// states in history/)
+fn remove_temp_files_in_tree(dir: &Path) -> usize {
+    let subdirs: usize = match fs::read_dir(dir) {
+        Err(_) => return 0,
+        Ok(e) => e.filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .map(|p| remove_temp_files_in_tree(&p))
+            .sum(),
+    };
+    subdirs + remove_temp_files(dir)
+}
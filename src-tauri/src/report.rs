@@ -0,0 +1,92 @@
+use serde::{Serialize, Deserialize};
+
+use crate::duplicate::DuplicateCandidate;
+use crate::errors::MaplineError;
+use crate::import::FileImport;
+
+/// Result of importing a single file or track
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum ImportOutcome {
+    Imported { ulids: Vec<String> },
+    Duplicate { candidate: DuplicateCandidate },
+    NotAnActivity,
+    ParseError { message: String },
+    Unsupported,
+    Failed { message: String }, // file could not be read or written
+    Cancelled,
+}
+
+impl From<MaplineError> for ImportOutcome {
+    fn from(err: MaplineError) -> ImportOutcome {
+        match err {
+            MaplineError::TrackAlreadyImported(candidate) => ImportOutcome::Duplicate { candidate },
+            MaplineError::FitFileNotAnActivity => ImportOutcome::NotAnActivity,
+            MaplineError::UnsupportedFormat => ImportOutcome::Unsupported,
+            MaplineError::Cancelled => ImportOutcome::Cancelled,
+            MaplineError::ParseError(message) => ImportOutcome::ParseError { message },
+            MaplineError::NoTrackPoints => ImportOutcome::ParseError { message: "no track points".to_string() },
+            e => ImportOutcome::Failed { message: format!("{:?}", e) },
+        }
+    }
+}
+
+/// Track of a file that was skipped, the other tracks of the file are imported
+#[derive(Serialize, Clone, Debug)]
+pub struct PartReport {
+    pub part: usize, // index of the track in the file
+    #[serde(flatten)]
+    pub outcome: ImportOutcome,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FileReport {
+    pub path: String, // path of the file, or of the entry inside an archive
+    pub archive: Option<String>, // path of the archive containing the entry
+    pub outcome: ImportOutcome, // of the first skipped track if no track was imported
+    pub parts: Vec<PartReport>,
+}
+
+impl FileReport {
+    fn new(path: &str, archive: Option<&str>, result: Result<FileImport, MaplineError>) -> FileReport {
+        let (outcome, parts) = match result {
+            Err(e) => (ImportOutcome::from(e), vec![]),
+            Ok(f) => {
+                let parts: Vec<PartReport> = f.skipped.into_iter()
+                    .map(|(part, e)| PartReport { part, outcome: ImportOutcome::from(e) })
+                    .collect();
+                let outcome = match (f.written.is_empty(), parts.first()) {
+                    (true, Some(p)) => p.outcome.clone(),
+                    _ => ImportOutcome::Imported { ulids: f.written.into_iter().map(|ta| ta.ulid).collect() },
+                };
+                (outcome, parts)
+            }
+        };
+        FileReport { path: path.to_string(), archive: archive.map(|a| a.to_string()), outcome, parts }
+    }
+}
+
+/// File or archive entry of a report to import again. Parts are the indices of
+/// the tracks to import, all tracks of the file without.
+#[derive(Deserialize, Clone, Debug)]
+pub struct RetryFile {
+    pub path: String,
+    pub parts: Option<Vec<usize>>,
+}
+
+/// Outcome of every file of a batch import, sent to the ui as "import_report"
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ImportReport {
+    pub files: Vec<FileReport>,
+}
+
+impl ImportReport {
+    pub fn add(&mut self, path: &str, result: Result<FileImport, MaplineError>) {
+        self.files.push(FileReport::new(path, None, result));
+    }
+
+    /// Entry of an archive, retried by importing the entry from the archive again
+    pub fn add_entry(&mut self, archive: &str, entry: &str, result: Result<FileImport, MaplineError>) {
+        self.files.push(FileReport::new(entry, Some(archive), result));
+    }
+}
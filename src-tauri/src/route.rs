@@ -0,0 +1,327 @@
+use geo::{HaversineDistance, Point};
+use geojson::GeoJson;
+use gpx::{Gpx, Track, TrackSegment, Waypoint};
+use serde::{Serialize, Deserialize};
+use ulid::Ulid;
+
+use crate::{distance, elevation, history, index, io, paths, segment};
+use crate::history::Operation;
+use crate::errors::MaplineError;
+use crate::import::AnalyzedTrack;
+use crate::line::arrange_display;
+use crate::lock::TrackLocks;
+use crate::track_analysis::TrackAnalysis;
+
+/// Max difference in meters of the start, end and distance of a route that is
+/// already in the library
+const SAME_ROUTE_TOLERANCE: f64 = 1.;
+
+/// Planned route (e.g. gpx <rte>), stored next to the tracks. Routes have no
+/// timestamps, so there are no time and speed values.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Route {
+    pub version: i32,
+    pub ulid: String,
+    pub name: Option<String>,
+    pub comment: Option<String>,
+    pub description: Option<String>,
+    pub source: Option<String>,
+    pub x_min: (f64, f64),
+    pub x_max: (f64, f64),
+    pub y_min: (f64, f64),
+    pub y_max: (f64, f64),
+    pub start_coords: (f64, f64),
+    pub end_coords: (f64, f64),
+    pub distance: f64, // in meters
+    pub ele_gain: Option<f64>, // from the elevation model
+    pub ele_loss: Option<f64>,
+    pub ele_max: Option<f64>,
+    pub ele_min: Option<f64>,
+}
+
+/// Point of interest, imported from waypoints (gpx <wpt>)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Poi {
+    pub id: String,
+    pub name: Option<String>,
+    pub comment: Option<String>,
+    pub description: Option<String>,
+    pub symbol: Option<String>,
+    pub coords: (f64, f64),
+    pub elevation: Option<f64>,
+}
+
+/// Planned vs. actual values of a track and the route it followed
+#[derive(Serialize, Clone, Debug)]
+pub struct RouteComparison {
+    pub route: String,
+    pub planned_distance: f64, // in meters
+    pub actual_distance: f64,
+    pub planned_ele_gain: Option<f64>,
+    pub actual_ele_gain: Option<f64>,
+    pub planned_ele_loss: Option<f64>,
+    pub actual_ele_loss: Option<f64>,
+}
+
+/// Routes and waypoints of a file, read by the importer next to the tracks
+#[derive(Default)]
+pub struct Planned {
+    pub routes: Vec<gpx::Route>,
+    pub waypoints: Vec<Waypoint>,
+}
+
+impl Planned {
+    pub fn from_gpx(gpx: &Gpx) -> Planned {
+        Planned {
+            routes: gpx.routes.iter().filter(|r| !r.points.is_empty()).cloned().collect(),
+            waypoints: gpx.waypoints.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty() && self.waypoints.is_empty()
+    }
+
+    /// Splits the lines of formats without a route element (kml, geojson):
+    /// lines without any timestamp are planned routes, not recordings
+    pub fn split_untimed(gpxs: Vec<Gpx>) -> (Vec<Gpx>, Planned) {
+        let mut tracks: Vec<Gpx> = vec![];
+        let mut planned = Planned::default();
+        for gpx in gpxs {
+            if segment::points(&gpx).iter().any(|p| p.time.is_some()) {
+                tracks.push(gpx);
+                continue;
+            }
+            for t in gpx.tracks {
+                let mut rte = gpx::Route::default();
+                rte.name = t.name;
+                rte.description = t.description;
+                rte.points = t.segments.into_iter().flat_map(|s| s.points).collect();
+                if !rte.points.is_empty() {
+                    planned.routes.push(rte);
+                }
+            }
+        }
+        (tracks, planned)
+    }
+}
+
+/// Route with its geometry and elevation profile, ready to be written
+pub struct AnalyzedRoute {
+    pub route: Route,
+    pub geojson: GeoJson,
+    pub gpx: Gpx,
+    pub elevation: Vec<(f64, f64)>,
+    pub elevation_coords: Vec<(f64, f64)>,
+}
+
+/// Analyzes the route (including the elevation lookup) without writing anything.
+/// The route points are stored as a gpx track with one segment, so the display
+/// and distance functions of the tracks can be used.
+pub fn analyze(rte: gpx::Route) -> Result<AnalyzedRoute, MaplineError> {
+    let mut track = Track::new();
+    track.name = rte.name.clone();
+    let mut segment = TrackSegment::new();
+    segment.points = rte.points.clone();
+    track.segments.push(segment);
+    let mut gpx = Gpx::default();
+    gpx.version = gpx::GpxVersion::Gpx11;
+    gpx.tracks.push(track);
+
+    let points = segment::points(&gpx);
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(f), Some(l)) => (f.point(), l.point()),
+        _ => return Err(MaplineError::NoTrackPoints),
+    };
+    let coords: Vec<(f64, f64)> = points.iter().map(|p| (p.point().x(), p.point().y())).collect();
+    let extreme = |better: &dyn Fn(&(f64, f64), &(f64, f64)) -> bool| {
+        coords.iter().fold(coords[0], |e, c| if better(c, &e) { *c } else { e })
+    };
+    let x_min = extreme(&|c, e| c.0 < e.0);
+    let x_max = extreme(&|c, e| c.0 > e.0);
+    let y_min = extreme(&|c, e| c.1 < e.1);
+    let y_max = extreme(&|c, e| c.1 > e.1);
+
+    let (elevation, ele_gain, ele_loss, ele_max, ele_min, elevation_coords) = match elevation::from_latlong(&gpx, &vec![]) {
+        Ok(e) => (e.0, Some(e.1), Some(e.2), Some(e.3), Some(e.4), e.5),
+        Err(e) => {
+            println!("{:?}", e);
+            (vec![], None, None, None, None, vec![])
+        }
+    };
+
+    let route = Route {
+        version: crate::ANALYSIS_VERSION,
+        ulid: Ulid::new().to_string(),
+        name: rte.name,
+        comment: rte.comment,
+        description: rte.description,
+        source: rte.source,
+        x_min,
+        x_max,
+        y_min,
+        y_max,
+        start_coords: (first.x(), first.y()),
+        end_coords: (last.x(), last.y()),
+        distance: distance::calculate(&gpx, &vec![]),
+        ele_gain,
+        ele_loss,
+        ele_max,
+        ele_min,
+    };
+    let geojson = arrange_display(&gpx, None, None);
+    Ok(AnalyzedRoute { route, geojson, gpx, elevation, elevation_coords })
+}
+
+pub fn analyze_all(routes: Vec<gpx::Route>) -> Result<Vec<AnalyzedRoute>, MaplineError> {
+    routes.into_iter().map(analyze).collect()
+}
+
+/// Writes the routes that are not yet in the library. If the file contains
+/// exactly one route, its tracks are linked to it (e.g. a planned route exported
+/// together with the recording). The tracks are written afterwards, see
+/// import::write_with_planned.
+pub fn write_all(routes: Vec<AnalyzedRoute>, tracks: &mut [AnalyzedTrack]) -> Result<Vec<Route>, MaplineError> {
+    let library = load_routes();
+    let mut result: Vec<Route> = vec![];
+    let mut ulids: Vec<String> = vec![];
+    for r in routes {
+        match library.iter().find(|l| same_route(l, &r.route)) {
+            Some(existing) => ulids.push(existing.ulid.clone()),
+            None => {
+                ulids.push(r.route.ulid.clone());
+                match write(r) {
+                    Ok(route) => result.push(route),
+                    Err(e) => {
+                        remove_all(&result);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+    if let [ulid] = &ulids[..] {
+        for t in tracks.iter_mut() {
+            t.analysis.planned_route = Some(ulid.clone());
+        }
+    }
+    Ok(result)
+}
+
+/// Removes routes written by write_all, e.g. if the tracks of the file failed
+pub fn remove_all(routes: &[Route]) {
+    for r in routes {
+        if let Err(e) = std::fs::remove_dir_all(paths::route(&r.ulid)) {
+            println!("Could not remove route {}: {:?}", r.ulid, e);
+        }
+    }
+}
+
+fn write(route: AnalyzedRoute) -> Result<Route, MaplineError> {
+    let AnalyzedRoute { route, geojson, gpx, elevation, elevation_coords } = route;
+    let result = io::write_route(&route)
+        .and_then(|_| io::write_route_elevation(elevation, elevation_coords, &route.ulid))
+        .and_then(|_| io::write_route_geojson(&geojson, &route.ulid))
+        .and_then(|_| io::write_route_gpx(&gpx, &route.ulid));
+    if let Err(e) = result {
+        std::fs::remove_dir_all(paths::route(&route.ulid)).ok();
+        return Err(MaplineError::WriteError(e.to_string()));
+    }
+    Ok(route)
+}
+
+fn same_route(a: &Route, b: &Route) -> bool {
+    let close = |x: (f64, f64), y: (f64, f64)| Point::from(x).haversine_distance(&Point::from(y)) < SAME_ROUTE_TOLERANCE;
+    close(a.start_coords, b.start_coords)
+        && close(a.end_coords, b.end_coords)
+        && (a.distance - b.distance).abs() < SAME_ROUTE_TOLERANCE
+}
+
+/// Adds the waypoints to the points of interest, waypoints at the position of
+/// an existing point with the same name are skipped
+pub fn add_pois(waypoints: Vec<Waypoint>) -> Result<(), MaplineError> {
+    if waypoints.is_empty() {
+        return Ok(());
+    }
+    let mut pois = io::read_pois().unwrap_or_default();
+    for w in waypoints {
+        let coords = (w.point().x(), w.point().y());
+        if pois.iter().any(|p| p.coords == coords && p.name == w.name) {
+            continue;
+        }
+        pois.push(Poi {
+            id: Ulid::new().to_string(),
+            name: w.name,
+            comment: w.comment,
+            description: w.description,
+            symbol: w.symbol,
+            coords,
+            elevation: w.elevation,
+        });
+    }
+    io::write_pois(&pois).map_err(|e| MaplineError::WriteError(e.to_string()))
+}
+
+pub fn load_routes() -> Vec<Route> {
+    let dirs = match std::fs::read_dir(paths::routes()) {
+        Err(_) => return vec![],
+        Ok(d) => d,
+    };
+    dirs.filter_map(|d| d.ok())
+        .filter(|d| d.path().is_dir())
+        .filter_map(|d| io::read_route(&d.file_name().to_string_lossy()).ok())
+        .collect()
+}
+
+/// Links a track to the route it followed, or removes the link
+pub fn link(ulid: &String, route: Option<String>) -> Result<TrackAnalysis, MaplineError> {
+    let mut ta = io::read_track_analysis(ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    if let Some(r) = &route {
+        if !paths::route_analysis(r).exists() {
+            return Err(MaplineError::FileError(format!("route {} not found", r)));
+        }
+    }
+    ta.planned_route = route;
+    io::write_track_analysis(&ta).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    Ok(ta)
+}
+
+/// Compares a track with its linked route
+pub fn compare(ulid: &String) -> Option<RouteComparison> {
+    let ta = io::read_track_analysis(ulid).ok()?;
+    let route = io::read_route(ta.planned_route.as_ref()?).ok()?;
+    Some(RouteComparison {
+        route: route.ulid,
+        planned_distance: route.distance,
+        actual_distance: ta.distance,
+        planned_ele_gain: route.ele_gain,
+        actual_ele_gain: ta.ele_gain,
+        planned_ele_loss: route.ele_loss,
+        actual_ele_loss: ta.ele_loss,
+    })
+}
+
+/// Removes the route and the links of all tracks to it, archived and trashed
+/// tracks included. Every unlink is an undoable edit of the track, the route is
+/// only removed once all tracks are unlinked.
+pub fn delete(ulid: &str, locks: &TrackLocks) -> Result<(), MaplineError> {
+    let tracks = index::all().unwrap_or_else(|e| {
+        println!("{:?}", e);
+        index::read_from_disk()
+    });
+    let linked: Vec<String> = tracks.into_iter()
+        .filter(|ta| ta.planned_route.as_deref() == Some(ulid))
+        .map(|ta| ta.ulid)
+        .collect();
+    for t in &linked {
+        locks.with(&[t.clone()], || {
+            // the index may be stale, the link is checked on the analysis itself
+            let ta = io::read_track_analysis(t).map_err(|e| MaplineError::FileError(e.to_string()))?;
+            if ta.planned_route.as_deref() != Some(ulid) {
+                return Ok(());
+            }
+            history::edit(t, Operation::Route, || link(t, None)).map(|_| ())
+        })?;
+    }
+    std::fs::remove_dir_all(paths::route(ulid)).map_err(|e| MaplineError::FileError(e.to_string()))
+}
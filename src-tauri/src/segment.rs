@@ -0,0 +1,11 @@
+use gpx::{Gpx, Waypoint};
+
+/// Returns the waypoints of all tracks and segments in recorded order.
+/// Indices into this vector are used by pauses, distance and elevation.
+pub fn points(gpx: &Gpx) -> Vec<&Waypoint> {
+    gpx.tracks.iter()
+        .flat_map(|t| t.segments.iter())
+        .flat_map(|s| s.points.iter())
+        .collect()
+}
+
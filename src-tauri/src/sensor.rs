@@ -0,0 +1,64 @@
+use geo::HaversineDistance;
+use gpx::Gpx;
+use serde::{Serialize, Deserialize};
+
+use crate::segment;
+
+/// Device channels recorded alongside the gps points. Every vector has one
+/// entry per point of record.gpx (in the order of segment::points).
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SensorData {
+    pub heart_rate: Vec<Option<f64>>, // in bpm
+    pub cadence: Vec<Option<f64>>, // in rpm
+    pub power: Vec<Option<f64>>, // in watts
+    pub temperature: Vec<Option<f64>>, // in degrees celsius
+    pub speed: Vec<Option<f64>>, // in m/s
+    pub altitude: Vec<Option<f64>>, // in meters
+}
+
+/// Values of a single record message
+#[derive(Clone, Debug, Default)]
+pub struct SensorRecord {
+    pub heart_rate: Option<f64>,
+    pub cadence: Option<f64>,
+    pub power: Option<f64>,
+    pub temperature: Option<f64>,
+    pub speed: Option<f64>,
+    pub altitude: Option<f64>,
+}
+
+impl SensorData {
+    pub fn push(&mut self, record: SensorRecord) {
+        self.heart_rate.push(record.heart_rate);
+        self.cadence.push(record.cadence);
+        self.power.push(record.power);
+        self.temperature.push(record.temperature);
+        self.speed.push(record.speed);
+        self.altitude.push(record.altitude);
+    }
+
+    /// True if at least one channel contains a value
+    pub fn has_values(&self) -> bool {
+        [&self.heart_rate, &self.cadence, &self.power, &self.temperature, &self.speed, &self.altitude]
+            .iter()
+            .any(|c| c.iter().any(|v| v.is_some()))
+    }
+}
+
+/// Cumulative distance in kilometers for every point of the gpx. Used as x-axis
+/// when displaying sensor data next to the elevation profile.
+pub fn point_distances(gpx: &Gpx) -> Vec<f64> {
+    let points = segment::points(gpx);
+    let mut result: Vec<f64> = Vec::with_capacity(points.len());
+    let mut dist = 0.;
+    let mut last_p = match points.first() {
+        Some(p) => *p,
+        None => return result,
+    };
+    for p in points {
+        dist += last_p.point().haversine_distance(&p.point());
+        result.push(dist / 1000.);
+        last_p = p;
+    }
+    result
+}
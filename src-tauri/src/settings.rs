@@ -1,15 +1,26 @@
 use std::path::PathBuf;
-use std::fs::{File, self};
-use std::io::{self, Write};
+use std::io;
 
 use serde::{Serialize, Deserialize};
 
-use crate::paths;
+use crate::csv_track::CsvColumns;
+use crate::{migrate, paths};
+
+/// Days a track stays in the trash before it is purged
+const DEFAULT_TRASH_DAYS: u32 = 30;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Settings {
     pub version: i32,
     pub import_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub csv_columns: CsvColumns,
+    #[serde(default = "default_trash_days")]
+    pub trash_days: u32,
+}
+
+fn default_trash_days() -> u32 {
+    DEFAULT_TRASH_DAYS
 }
 
 impl Settings {
@@ -17,6 +28,8 @@ impl Settings {
         Settings {
             version: crate::SETTINGS_VERSION,
             import_paths: vec![],
+            csv_columns: CsvColumns::default(),
+            trash_days: DEFAULT_TRASH_DAYS,
         }
     }
     pub fn load() -> Result<Settings, std::io::Error> {
@@ -28,8 +41,14 @@ impl Settings {
             settings.write();
             return Ok(settings)
         }
-        let json_string = fs::read_to_string(path)?;
-        let s: Settings = serde_json::from_str(&json_string.as_str())?;
+        // settings of an older version are upgraded and written back
+        let (s, upgraded): (Settings, bool) = match migrate::settings(&path) {
+            Ok(r) => r,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))),
+        };
+        if upgraded {
+            s.write()?;
+        }
         Ok(s)
     }
 
@@ -38,12 +57,15 @@ impl Settings {
         self.write();
     }
 
+    pub fn set_csv_columns(&mut self, columns: CsvColumns) {
+        self.csv_columns = columns;
+        self.write();
+    }
+
     fn write(&self) -> Result<(), io::Error> {
         let mut path = paths::maplines();
         path.push("settings");
         path.set_extension("json");
-        let mut file = File::create(path).unwrap();
-        write!(file, "{}", serde_json::to_string(self)?)?;
-        Ok(())
+        crate::io::write_atomic(&path, serde_json::to_string(self)?.as_bytes())
     }
 }
\ No newline at end of file
@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+/// Original file a track was imported from. A copy of the file is kept in the
+/// track folder, so the track can be re-imported once the importers improve.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SourceFile {
+    pub file_name: String,
+    pub format: String, // extension used to select the importer
+    pub checksum: String, // sha256 of the (decompressed) file
+    pub part: usize, // index of the track within the file (e.g. leg of a multisport file)
+}
+
+/// Source file together with its content. The content is shared by all tracks of a file.
+#[derive(Clone, Debug)]
+pub struct Source {
+    pub file: SourceFile,
+    pub data: Arc<Vec<u8>>,
+}
+
+pub fn checksum(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
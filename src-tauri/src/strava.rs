@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use crate::errors::MaplineError;
+use crate::import::{self, FileImport};
+use crate::report::{ImportReport, RetryFile};
+use crate::track_analysis::{self, TrackAnalysis};
+
+const ACTIVITIES_CSV: &str = "activities.csv";
+const ACTIVITIES_DIR: &str = "activities/";
+
+/// Name, description and type of an activity from activities.csv
+struct StravaActivity {
+    name: Option<String>,
+    activity_type: Option<String>,
+    description: Option<String>,
+}
+
+/// Imports the activities of a strava bulk export archive, all of them or only
+/// the given entries and their parts (e.g. to retry them). on_import is called for every
+/// imported track, the report lists the outcome of every activity file.
+pub fn import<F>(archive_path: &PathBuf, entries: Option<&[RetryFile]>, force: bool, mut on_import: F) -> Result<ImportReport, MaplineError>
+where F: FnMut(&TrackAnalysis) {
+    let file = match File::open(archive_path) {
+        Err(err) => return Err(MaplineError::FileError(err.to_string())),
+        Ok(f) => f,
+    };
+    let mut archive = match zip::ZipArchive::new(BufReader::new(file)) {
+        Err(err) => return Err(MaplineError::ArchiveError(err.to_string())),
+        Ok(a) => a,
+    };
+    let activities = match archive.by_name(ACTIVITIES_CSV) {
+        Ok(csv_file) => read_activities_csv(csv_file)?,
+        Err(_e) => HashMap::new(),
+    };
+
+    let archive_name = archive_path.to_string_lossy().to_string();
+    let mut report = ImportReport::default();
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Err(err) => return Err(MaplineError::ArchiveError(err.to_string())),
+            Ok(e) => e,
+        };
+        let name = entry.name().to_string();
+        if !name.starts_with(ACTIVITIES_DIR) || entry.is_dir() {
+            continue;
+        }
+        let parts = match entries.map(|e| e.iter().find(|f| f.path == name)) {
+            Some(None) => continue,
+            Some(Some(f)) => f.parts.clone(),
+            None => None,
+        };
+        let mut data: Vec<u8> = vec![];
+        if let Err(err) = entry.read_to_end(&mut data) {
+            report.add_entry(&archive_name, &name, Err(MaplineError::FileError(err.to_string())));
+            continue;
+        }
+        let result = import_entry(&name, data, activities.get(&name), force, parts.as_deref());
+        if let Ok(file) = &result {
+            for ta in &file.written { on_import(ta); }
+        }
+        report.add_entry(&archive_name, &name, result);
+    }
+    Ok(report)
+}
+
+fn import_entry(name: &str, data: Vec<u8>, activity: Option<&StravaActivity>, force: bool, parts: Option<&[usize]>) -> Result<FileImport, MaplineError> {
+    let data = import::decompress(data)?;
+    let extension = import::extension(Path::new(name));
+    let mut tracks = import::parse(&data, &extension)?;
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    import::attach_source(&mut tracks, data, file_name, &extension);
+    if let Some(a) = activity {
+        for t in tracks.iter_mut() {
+            if let Some(track) = t.gpx.tracks.first_mut() {
+                if a.name.is_some() { track.name = a.name.clone(); }
+                if a.description.is_some() { track.description = a.description.clone(); }
+            }
+            if let Some(activity_type) = &a.activity_type {
+                t.activity = Some(track_analysis::activity_type_from_strava(activity_type));
+            }
+        }
+    }
+    import::store_all(tracks, force, parts)
+}
+
+/// Returns the activities of the csv by file name (e.g. activities/123.fit.gz)
+fn read_activities_csv<R: Read>(reader: R) -> Result<HashMap<String, StravaActivity>, MaplineError> {
+    let mut csv_reader = csv::Reader::from_reader(reader);
+    let headers = match csv_reader.headers() {
+        Err(err) => return Err(MaplineError::ArchiveError(err.to_string())),
+        Ok(h) => h.clone(),
+    };
+    // newer exports contain some column names twice, the first one is used
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let (file_col, name_col, type_col, desc_col) = (column("Filename"), column("Activity Name"), column("Activity Type"), column("Activity Description"));
+    let file_col = match file_col {
+        Some(c) => c,
+        None => return Err(MaplineError::ArchiveError("activities.csv has no Filename column".to_string())),
+    };
+
+    let mut result: HashMap<String, StravaActivity> = HashMap::new();
+    for record in csv_reader.records() {
+        let record = match record {
+            Err(_e) => continue,
+            Ok(r) => r,
+        };
+        let value = |col: Option<usize>| col
+            .and_then(|c| record.get(c))
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_string());
+        let file = match value(Some(file_col)) {
+            Some(f) => f,
+            None => continue, // manual activities without gps file
+        };
+        result.insert(file, StravaActivity {
+            name: value(name_col),
+            activity_type: value(type_col),
+            description: value(desc_col),
+        });
+    }
+    Ok(result)
+}
@@ -0,0 +1,39 @@
+use serde::{Serialize, Deserialize};
+
+/// Totals reported by the recording device (e.g. the session message of a fit file).
+/// Units are the same as in TrackAnalysis.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DeviceSummary {
+    pub time_moving: Option<u64>, // total timer time in seconds
+    pub time_total: Option<u64>, // total elapsed time in seconds
+    pub distance: Option<f64>, // in meters
+    pub avg_vel: Option<f64>, // in kph
+    pub max_vel: Option<f64>, // in kph
+    pub ele_gain: Option<f64>,
+    pub ele_loss: Option<f64>,
+    pub avg_heart_rate: Option<f64>,
+    pub max_heart_rate: Option<f64>,
+    pub avg_cadence: Option<f64>,
+    pub avg_power: Option<f64>,
+    pub calories: Option<f64>,
+}
+
+/// Which values are displayed for a track
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SummarySource {
+    Computed,
+    Device,
+}
+
+impl Default for SummarySource {
+    fn default() -> Self {
+        SummarySource::Computed
+    }
+}
+
+pub fn summary_source_from_string(source: &String) -> SummarySource {
+    match source.to_lowercase().as_str() {
+        "device" => SummarySource::Device,
+        _ => SummarySource::Computed,
+    }
+}
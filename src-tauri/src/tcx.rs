@@ -0,0 +1,201 @@
+use std::io::{Cursor, Read};
+
+use chrono::prelude::{DateTime, Utc};
+use geo_types::Point;
+use gpx::{Gpx, Time, Track, TrackSegment, Waypoint};
+use time::OffsetDateTime;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::errors::MaplineError;
+use crate::import::{Extras, ParsedTrack};
+use crate::importer::{self, FormatInfo, Importer};
+use crate::lap::Lap;
+use crate::sensor::{SensorData, SensorRecord};
+use crate::track_analysis::Activity;
+
+pub struct TcxImporter;
+
+impl Importer for TcxImporter {
+    fn info(&self) -> FormatInfo {
+        FormatInfo { id: "tcx", name: "TCX", extensions: &["tcx"], column_mapping: false }
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        importer::xml_root(data, "<TrainingCenterDatabase")
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<Vec<ParsedTrack>, MaplineError> {
+        let activities = read(Cursor::new(data))?;
+        Ok(activities.into_iter().map(|a| ParsedTrack {
+            gpx: a.gpx,
+            activity: Some(a.activity),
+            extras: Extras { sensor_data: a.sensor_data, laps: a.laps, ..Default::default() },
+            source: None,
+        }).collect())
+    }
+}
+
+/// A single <Activity> of a Training Center XML file
+pub struct TcxActivity {
+    pub gpx: Gpx,
+    pub activity: Activity,
+    pub sensor_data: SensorData,
+    pub laps: Vec<Lap>,
+}
+
+/// Values of a <Trackpoint>. Points without position are skipped.
+#[derive(Default)]
+struct TcxPoint {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    time: Option<Time>,
+    record: SensorRecord,
+}
+
+/// Reads all activities with their laps and trackpoints, including the
+/// heart rate, cadence and Garmin TPX / LX extensions.
+pub fn read<R: Read>(reader: R) -> Result<Vec<TcxActivity>, MaplineError> {
+    let mut result: Vec<TcxActivity> = vec![];
+    let mut stack: Vec<String> = vec![];
+
+    let mut current: Option<TcxActivity> = None;
+    let mut lap: Option<Lap> = None;
+    let mut point: Option<TcxPoint> = None;
+    let mut point_count: usize = 0;
+
+    for e in EventReader::new(reader) {
+        match e {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                match name.local_name.as_str() {
+                    "Activity" => {
+                        let sport = attributes.iter()
+                            .find(|a| a.name.local_name == "Sport")
+                            .map(|a| a.value.clone())
+                            .unwrap_or_default();
+                        let mut gpx = Gpx::default();
+                        gpx.version = gpx::GpxVersion::Gpx11;
+                        gpx.tracks.push(Track::new());
+                        current = Some(TcxActivity { gpx, activity: activity_from_sport(&sport), sensor_data: SensorData::default(), laps: vec![] });
+                        point_count = 0;
+                    }
+                    "Lap" => {
+                        let start_time = attributes.iter()
+                            .find(|a| a.name.local_name == "StartTime")
+                            .map(|a| a.value.clone());
+                        lap = Some(Lap {
+                            start_index: Some(point_count),
+                            end_index: None,
+                            start_time,
+                            duration_sec: None,
+                            distance: None,
+                            avg_vel: None,
+                            avg_heart_rate: None,
+                            avg_cadence: None,
+                            avg_power: None,
+                        });
+                    }
+                    "Track" => {
+                        if let Some(a) = current.as_mut() {
+                            a.gpx.tracks[0].segments.push(TrackSegment::new());
+                        }
+                    }
+                    "Trackpoint" => point = Some(TcxPoint::default()),
+                    _ => (),
+                }
+                stack.push(name.local_name);
+            }
+            Ok(XmlEvent::Characters(text)) => {
+                let tag = stack.last().map(|s| s.as_str()).unwrap_or("");
+                let parent = if stack.len() > 1 { stack[stack.len() - 2].as_str() } else { "" };
+                let value = text.trim();
+                if let Some(p) = point.as_mut() {
+                    match (parent, tag) {
+                        ("Trackpoint", "Time") => p.time = parse_time(value),
+                        ("Position", "LatitudeDegrees") => p.lat = value.parse::<f64>().ok(),
+                        ("Position", "LongitudeDegrees") => p.lon = value.parse::<f64>().ok(),
+                        ("Trackpoint", "AltitudeMeters") => p.record.altitude = value.parse::<f64>().ok(),
+                        ("HeartRateBpm", "Value") => p.record.heart_rate = value.parse::<f64>().ok(),
+                        ("Trackpoint", "Cadence") => p.record.cadence = value.parse::<f64>().ok(),
+                        (_, "RunCadence") => p.record.cadence = value.parse::<f64>().ok(),
+                        (_, "Watts") => p.record.power = value.parse::<f64>().ok(),
+                        (_, "Speed") => p.record.speed = value.parse::<f64>().ok(),
+                        _ => (),
+                    }
+                } else if let Some(l) = lap.as_mut() {
+                    match (parent, tag) {
+                        ("Lap", "TotalTimeSeconds") => l.duration_sec = value.parse::<f64>().ok(),
+                        ("Lap", "DistanceMeters") => l.distance = value.parse::<f64>().ok(),
+                        ("Lap", "Cadence") => l.avg_cadence = value.parse::<f64>().ok(),
+                        ("AverageHeartRateBpm", "Value") => l.avg_heart_rate = value.parse::<f64>().ok(),
+                        (_, "AvgSpeed") => l.avg_vel = value.parse::<f64>().ok().map(|s| s * 3.6),
+                        (_, "AvgWatts") => l.avg_power = value.parse::<f64>().ok(),
+                        _ => (),
+                    }
+                } else if let Some(a) = current.as_mut() {
+                    if (parent, tag) == ("Creator", "Name") {
+                        a.gpx.creator = Some(value.to_string());
+                    }
+                }
+            }
+            Ok(XmlEvent::EndElement { name }) => {
+                stack.pop();
+                match name.local_name.as_str() {
+                    "Trackpoint" => {
+                        if let (Some(p), Some(a)) = (point.take(), current.as_mut()) {
+                            if let (Some(lat), Some(lon)) = (p.lat, p.lon) {
+                                let mut waypoint = Waypoint::new(Point::new(lon, lat));
+                                waypoint.time = p.time;
+                                waypoint.elevation = p.record.altitude;
+                                if a.gpx.tracks[0].segments.is_empty() {
+                                    a.gpx.tracks[0].segments.push(TrackSegment::new());
+                                }
+                                a.gpx.tracks[0].segments.last_mut().unwrap().points.push(waypoint);
+                                a.sensor_data.push(p.record);
+                                point_count += 1;
+                            }
+                        }
+                    }
+                    "Lap" => {
+                        if let (Some(mut l), Some(a)) = (lap.take(), current.as_mut()) {
+                            // laps without trackpoints keep their totals, but have no points
+                            if l.start_index.map(|s| point_count > s).unwrap_or(false) {
+                                l.end_index = Some(point_count - 1);
+                            } else {
+                                l.start_index = None;
+                            }
+                            a.laps.push(l);
+                        }
+                    }
+                    "Activity" => {
+                        if let Some(a) = current.take() {
+                            result.push(a);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Err(e) => return Err(MaplineError::ParseError(e.to_string())),
+            _ => (),
+        }
+    }
+    if result.is_empty() {
+        return Err(MaplineError::NoTrackPoints);
+    }
+    Ok(result)
+}
+
+fn parse_time(value: &str) -> Option<Time> {
+    let ti = value.parse::<DateTime<Utc>>().ok()?;
+    Some(Time::from(OffsetDateTime::from_unix_timestamp(ti.timestamp()).ok()?))
+}
+
+fn activity_from_sport(sport: &str) -> Activity {
+    match sport.to_lowercase().as_str() {
+        "running" => Activity::Running,
+        "biking" => Activity::Cycling,
+        "hiking" => Activity::Hiking,
+        "walking" => Activity::Hiking, // same activity as hiking
+        "swimming" => Activity::Swimming,
+        _ => Activity::Other,
+    }
+}
@@ -8,8 +8,12 @@ use std::path::PathBuf;
 use std::fs;
 use std::time::Duration;
 
-use crate::{distance, elevation, io};
+use crate::{distance, elevation, segment};
+use crate::elevation::ElevationSource;
 use crate::pause::{self, Pause};
+use crate::sensor::SensorData;
+use crate::flight::FlightSummary;
+use crate::summary::{DeviceSummary, SummarySource};
 /// same as Track but without links and segments
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TrackAnalysis {
@@ -39,6 +43,21 @@ pub struct TrackAnalysis {
     pub ele_max: Option<f64>,
     pub ele_min: Option<f64>,
     pub pauses: Vec<Pause>,
+    pub device_summary: Option<DeviceSummary>,
+    #[serde(default)]
+    pub summary_source: SummarySource,
+    pub multisport: Option<String>, // id of the multisport event
+    pub flight: Option<FlightSummary>,
+    #[serde(default)]
+    pub planned_route: Option<String>, // ulid of the route the track followed
+    #[serde(default)]
+    pub elevation_source: Option<ElevationSource>, // None for analyses written before the source could be chosen
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub archived: bool, // hidden from the list, still found by a search
+    #[serde(default)]
+    pub trashed: Option<i64>, // unix timestamp of the deletion, purged after Settings::trash_days
 }
 
 impl TrackAnalysis {
@@ -48,7 +67,11 @@ impl TrackAnalysis {
         Ok(ta)
     }
 
-    pub fn new(ulid: Option<String>, geojson: &GeoJson, gpx: &Gpx, activity: Option<Activity>) -> TrackAnalysis {
+    /// Analyzes the gpx. The elevation profile (and its coordinates) is returned
+    /// instead of written, so it is written together with the analysis.
+    /// The elevation stats and profile are taken from the source, device sources
+    /// read the altitudes of the gpx points or of the sensor data.
+    pub fn analyze(ulid: Option<String>, geojson: &GeoJson, gpx: &Gpx, activity: Option<Activity>, source: ElevationSource, sensor_data: &SensorData) -> (TrackAnalysis, Vec<(f64, f64)>, Vec<(f64, f64)>) {
 
         let track: Track = gpx.tracks[0].clone();
 
@@ -56,54 +79,66 @@ impl TrackAnalysis {
         let gj_geometry: geojson::Geometry = feature.geometry.unwrap();
 
         // Change to Result instead of panic
-        let coords = if let Value::LineString(coords) = gj_geometry.value { coords }
-                                    else { panic!("could not extract coords from geojson file"); };
-        let start = coords.first().unwrap();
+        let lines = match gj_geometry.value {
+            Value::MultiLineString(lines) => lines,
+            Value::LineString(coords) => vec![coords],
+            _ => panic!("could not extract coords from geojson file"),
+        };
+        let start = lines.first().unwrap().first().unwrap();
         let start_coords: (f64, f64) = (start.first().unwrap().to_owned(), start.last().unwrap().to_owned());
-        let end = coords.last().unwrap();
+        let end = lines.last().unwrap().last().unwrap();
         let end_coords: (f64, f64) = (end.first().unwrap().to_owned(), end.last().unwrap().to_owned());
-        let geo_line: geo::LineString<f64> = Value::LineString(coords).try_into().unwrap();
-        let geometry: geo::Geometry = geo_line.into();
+        let geo_lines: geo::MultiLineString<f64> = Value::MultiLineString(lines).try_into().unwrap();
+        let geometry: geo::Geometry = geo_lines.into();
         let extremes = geometry.extremes().unwrap();
         let activity = match activity {
             None => activity_type_from_track(&track),
             Some(a) => a,
         };
-        let pauses: Vec<Pause> = pause::find(gpx);
+        // circling in thermals would be detected as pauses
+        let pauses: Vec<Pause> = match activity {
+            Activity::Flying => vec![],
+            _ => pause::find(gpx),
+        };
         let distance = distance::calculate(gpx, &pauses);
-        let start_time = gpx.tracks[0].segments[0].points[0].time.unwrap();
-        let start_odt: OffsetDateTime = start_time.into();
-        let end_time = gpx.tracks[0].segments[0].points.last().unwrap().time.unwrap();
-        let end_odt: OffsetDateTime = end_time.into();
-        let time_total = (end_odt.unix_timestamp() - start_odt.unix_timestamp()).abs() as u64;
-        let time_moving = time_total - pauses.iter().map(|x| x.duration_sec).sum::<u64>();
-        let avg_vel: f64 = (distance / 1000.) / (time_moving as f64 / 3600.);
-        println!("avg_vel: {}", avg_vel);
-
-        // let duration = Duration::from_secs(secs)
-        // let time_total: std::time::Duration = end_time. - start_time;
+        let points = segment::points(gpx);
+        let start_time = points.first().unwrap().time;
+        let end_time = points.last().unwrap().time;
+        // tracks without timestamps (e.g. planned routes) have no time and speed values
+        let (time_total, time_moving, avg_vel) = match (start_time, end_time) {
+            (Some(s), Some(e)) => {
+                let start_odt: OffsetDateTime = s.into();
+                let end_odt: OffsetDateTime = e.into();
+                let time_total = (end_odt.unix_timestamp() - start_odt.unix_timestamp()).abs() as u64;
+                let time_moving = time_total.saturating_sub(pauses.iter().map(|x| x.duration_sec).sum::<u64>());
+                let avg_vel: f64 = (distance / 1000.) / (time_moving as f64 / 3600.);
+                println!("avg_vel: {}", avg_vel);
+                (Some(time_total), Some(time_moving), Some(avg_vel))
+            }
+            _ => (None, None, None),
+        };
 
-        let ulid = match ulid {
-            Some(u) => u,
-            None => Ulid::from_datetime(start_time.into()).to_string(),
+        let ulid = match (ulid, start_time) {
+            (Some(u), _) => u,
+            (None, Some(t)) => Ulid::from_datetime(t.into()).to_string(),
+            (None, None) => Ulid::new().to_string(),
         };
 
-        let (ele, ele_gain, ele_loss, ele_max, ele_min, coords) = match elevation::from_latlong(gpx, &pauses) {
+        let (ele, ele_gain, ele_loss, ele_max, ele_min, coords) = match elevation::from_source(source, gpx, sensor_data, &pauses) {
             Ok(e) => e,
             Err(e) => {
                 println!("{:?}", e);
                 (vec![], 0., 0., 0., 0., vec![])
             }
         };
-        io::write_elevation(ele, coords, &ulid);
 
-        TrackAnalysis {
+        let track_analysis = TrackAnalysis {
             version: crate::ANALYSIS_VERSION,
             ulid: ulid,
-            start_time: Some(start_time.format().unwrap()),
-            end_time: Some(end_time.format().unwrap()),
-            time_total: Some(time_total),
-            time_moving: Some(time_moving),
+            start_time: start_time.map(|t| t.format().unwrap()),
+            end_time: end_time.map(|t| t.format().unwrap()),
+            time_total,
+            time_moving,
             name: track.name.clone(),
             comment: track.comment.clone(),
             description: track.description.clone(),
@@ -118,13 +153,23 @@ impl TrackAnalysis {
             start_coords,
             end_coords,
             distance,
-            avg_vel: Some(avg_vel),
+            avg_vel,
             ele_gain: Some(ele_gain),
             ele_loss: Some(ele_loss),
             ele_max: Some(ele_max),
             ele_min: Some(ele_min),
             pauses,
-        }
+            device_summary: None,
+            summary_source: SummarySource::Computed,
+            multisport: None,
+            flight: None,
+            planned_route: None,
+            elevation_source: Some(source),
+            tags: vec![],
+            archived: false,
+            trashed: None,
+        };
+        (track_analysis, ele, coords)
     }
 }
 
@@ -136,6 +181,7 @@ pub enum Activity {
     InlineSkating,
     Running,
     Swimming,
+    Flying, // paragliding, hang gliding and gliding
     Other, // Default Value
 }
 
@@ -193,6 +239,19 @@ fn activity_type_from_track(track: &Track) -> Activity {
     }
 }
 
+/// Maps the activity types of the strava export csv
+pub fn activity_type_from_strava(activity: &str) -> Activity {
+    match activity.to_lowercase().as_str() {
+        "ride" | "e-bike ride" | "virtual ride" | "gravel ride" | "mountain bike ride" => Activity::Cycling,
+        "run" | "trail run" | "virtual run" => Activity::Running,
+        "hike" | "walk" => Activity::Hiking,
+        "nordic ski" => Activity::XCSkiing,
+        "inline skate" => Activity::InlineSkating,
+        "swim" => Activity::Swimming,
+        _ => Activity::Other,
+    }
+}
+
 pub fn activity_type_from_string(activity: &String) -> Activity {
     match activity.to_lowercase().as_str() {
         "xcskiing" => Activity::XCSkiing,
@@ -201,6 +260,7 @@ pub fn activity_type_from_string(activity: &String) -> Activity {
         "inlineskating" => Activity::InlineSkating,
         "running" => Activity::Running,
         "swimming" => Activity::Swimming,
+        "flying" => Activity::Flying,
         _ => Activity::Other,
     }
 }
\ No newline at end of file
@@ -11,6 +11,13 @@ pub struct TrackNote {
     pub pictures: Option<Vec<PathBuf>>,
 }
 
+/// Content of notes.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackNotes {
+    pub version: i32,
+    pub notes: Vec<TrackNote>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TrackIcon {
     Picture,
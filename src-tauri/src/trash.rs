@@ -0,0 +1,59 @@
+use chrono::prelude::Utc;
+
+use crate::{index, io, paths};
+use crate::errors::MaplineError;
+use crate::lock::TrackLocks;
+use crate::track_analysis::TrackAnalysis;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Moves the track to the trash. The folder stays in the library until the
+/// track is purged, so a restored track has all its files and notes.
+pub fn trash(ulid: &String) -> Result<TrackAnalysis, MaplineError> {
+    update(ulid, |ta| ta.trashed = Some(Utc::now().timestamp()))
+}
+
+pub fn restore(ulid: &String) -> Result<TrackAnalysis, MaplineError> {
+    update(ulid, |ta| ta.trashed = None)
+}
+
+/// Archived tracks are hidden from the list, but still found by a search
+pub fn set_archived(ulid: &String, archived: bool) -> Result<TrackAnalysis, MaplineError> {
+    update(ulid, |ta| ta.archived = archived)
+}
+
+fn update(ulid: &String, change: impl FnOnce(&mut TrackAnalysis)) -> Result<TrackAnalysis, MaplineError> {
+    let mut ta = io::read_track_analysis(ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    change(&mut ta);
+    io::write_track_analysis(&ta).map_err(|e| MaplineError::WriteError(e.to_string()))?;
+    Ok(ta)
+}
+
+/// Removes the folder of a trashed track for good. Tracks have to be trashed
+/// first, so a track is never lost by a single action.
+pub fn purge(ulid: &String) -> Result<(), MaplineError> {
+    let ta = io::read_track_analysis(ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    if ta.trashed.is_none() {
+        return Err(MaplineError::FileError(format!("track {} is not in the trash", ulid)));
+    }
+    std::fs::remove_dir_all(paths::track(ulid)).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    index::remove(ulid)
+}
+
+/// Purges the tracks trashed more than the given number of days ago, returns
+/// their ulids
+pub fn purge_expired(days: u32, locks: &TrackLocks) -> Vec<String> {
+    let trashed = index::trash().unwrap_or_else(|e| {
+        println!("{:?}", e);
+        index::read_from_disk().into_iter().filter(|ta| ta.trashed.is_some()).collect()
+    });
+    let before = Utc::now().timestamp() - days as i64 * SECONDS_PER_DAY;
+    let mut purged: Vec<String> = vec![];
+    for ta in trashed.iter().filter(|ta| ta.trashed.map(|t| t < before).unwrap_or(false)) {
+        match locks.with(&[ta.ulid.clone()], || purge(&ta.ulid)) {
+            Ok(()) => purged.push(ta.ulid.clone()),
+            Err(e) => println!("Could not purge track {}: {:?}", ta.ulid, e),
+        }
+    }
+    purged
+}
@@ -5,23 +5,22 @@ use gpx::Track;
 use crate::io::{self, write_track_analysis, write_geojson, write_gpx};
 use crate::line::arrange_display;
 use crate::track_analysis::{TrackAnalysis, self};
-use crate::{pause, elevation};
+use crate::{history, index, paths, pause, elevation, trash};
+use crate::history::Operation;
+use crate::elevation::{ElevationProfiles, ElevationSource};
+use crate::errors::MaplineError;
+use crate::sensor::SensorData;
 
 
-pub fn track_with_start_time_exists(start_time: &String) -> bool {
-    let track_analysis = crate::load_track_analysis();
-    for t in track_analysis {
-        if t.start_time.eq(&Some(start_time.clone())) {
-            return true;
-        }
-    }
-    false
-}
-
-pub fn join_tracks(ulids: Vec<String>) -> Option<()> {
-    let mut analysis: Vec<TrackAnalysis> = ulids.iter().map(|x| io::read_track_analysis(x).unwrap()).collect();
+/// Joins the tracks into a new track, the parts are moved to the trash
+pub fn join_tracks(ulids: Vec<String>) -> Result<TrackAnalysis, MaplineError> {
+    let read_error = |e: std::io::Error| MaplineError::FileError(e.to_string());
+    let gpx_error = || MaplineError::FileError("record.gpx could not be read".to_string());
+    let mut analysis: Vec<TrackAnalysis> = ulids.iter()
+        .map(|x| io::read_track_analysis(x).map_err(read_error))
+        .collect::<Result<Vec<TrackAnalysis>, MaplineError>>()?;
     // TODO: check if this sorting always works correctly
-    analysis.sort_by(|a, b| a.start_time.partial_cmp(&b.start_time).unwrap());
+    analysis.sort_by(|a, b| a.start_time.cmp(&b.start_time));
     
     // TODO: check if tracks do not overlap in time
     /*
@@ -37,37 +36,114 @@ pub fn join_tracks(ulids: Vec<String>) -> Option<()> {
     }
     */
 
-    let mut new_gpx = io::read_gpx(&analysis[0].ulid).unwrap();
-    analysis.remove(0);
+    if analysis.is_empty() {
+        return Err(MaplineError::FileError("no tracks to join".to_string()));
+    }
+    let mut new_gpx = io::read_gpx(&analysis.remove(0).ulid).ok_or_else(gpx_error)?;
     for a in analysis {
-        let next_part = io::read_gpx(&a.ulid).unwrap();
-        for p in &next_part.tracks[0].segments[0].points {
-            new_gpx.tracks[0].segments[0].points.push(p.clone());
+        let next_part = io::read_gpx(&a.ulid).ok_or_else(gpx_error)?;
+        let first = new_gpx.tracks.first_mut().ok_or(MaplineError::NoTrackPoints)?;
+        // every part keeps its segments, the gaps between them count as pauses
+        for t in next_part.tracks {
+            first.segments.extend(t.segments);
         }
     }
     let geojson = arrange_display(&new_gpx, None, None);
     
     // analyze geo data
-    let track_analysis = TrackAnalysis::new(None, &geojson, &new_gpx, None);
+    // sensor data is not joined, so only the gpx elevation or the elevation model can be used
+    let sensor_data = SensorData::default();
+    let source = elevation::default_source(&new_gpx, &sensor_data, false);
+    let (track_analysis, ele, coords) = TrackAnalysis::analyze(None, &geojson, &new_gpx, None, source, &sensor_data);
     let geojson = arrange_display(&new_gpx, Some(geojson), Some(&track_analysis.pauses));
-    write_track_analysis(&track_analysis).unwrap();
-    write_geojson(&geojson, &track_analysis.ulid).unwrap();
-    write_gpx(&new_gpx, &track_analysis.ulid).unwrap();
+    let ulid = &track_analysis.ulid;
+    let written = io::begin_write(ulid)
+        .and_then(|_| io::write_elevation(ele, coords, ulid))
+        .and_then(|_| write_track_analysis(&track_analysis))
+        .and_then(|_| write_geojson(&geojson, ulid))
+        .and_then(|_| write_gpx(&new_gpx, ulid))
+        .and_then(|_| io::end_write(ulid));
+    // the parts are untouched so far, the new track is removed again
+    if let Err(e) = written {
+        std::fs::remove_dir_all(paths::track(ulid)).ok();
+        index::remove(ulid).ok();
+        return Err(MaplineError::WriteError(e.to_string()));
+    }
 
-    println!("done");
-    Some(())
+    // the parts are moved to the trash, undoing the join restores them
+    for ulid in &ulids {
+        if let Err(e) = trash::trash(ulid) {
+            println!("{:?}", e);
+        }
+    }
+    if let Err(e) = history::record(&track_analysis.ulid, Operation::Join { parts: ulids }) {
+        println!("{:?}", e);
+    }
+    Ok(track_analysis)
 }
 
-pub fn recalculate_track(ulid: String) {
-    let gpx = io::read_gpx(&ulid).unwrap();
-    let old_ta = io::read_track_analysis(&ulid).unwrap();
+/// New analysis and geometry from record.gpx, the changes of the user are kept
+pub fn recalculate_track(ulid: String) -> Result<TrackAnalysis, MaplineError> {
+    let gpx = io::read_gpx(&ulid).ok_or_else(|| MaplineError::FileError("record.gpx could not be read".to_string()))?;
+    let old_ta = io::read_track_analysis(&ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
 
     let geojson = arrange_display(&gpx, None, None);
     
+    // the chosen elevation source is kept, older analyses get the automatic choice
+    let sensor_data = io::read_sensor_data(&ulid).unwrap_or_default();
+    let source = old_ta.elevation_source
+        .unwrap_or_else(|| elevation::default_source(&gpx, &sensor_data, old_ta.flight.is_some()));
+
     // analyze geo data
-    let ta = TrackAnalysis::new(Some(ulid), &geojson, &gpx, Some(old_ta._type));
+    let (mut ta, ele, coords) = TrackAnalysis::analyze(Some(ulid), &geojson, &gpx, Some(old_ta._type), source, &sensor_data);
+    ta.device_summary = old_ta.device_summary;
+    ta.summary_source = old_ta.summary_source;
+    ta.multisport = old_ta.multisport;
+    ta.flight = old_ta.flight;
+    ta.planned_route = old_ta.planned_route;
+    ta.tags = old_ta.tags;
+    ta.archived = old_ta.archived;
+    ta.trashed = old_ta.trashed;
     let geojson = arrange_display(&gpx, Some(geojson), Some(&ta.pauses));
     
-    write_track_analysis(&ta).unwrap();
-    write_geojson(&geojson, &ta.ulid).unwrap();
-}
\ No newline at end of file
+    let write_error = |e: std::io::Error| MaplineError::WriteError(e.to_string());
+    io::begin_write(&ta.ulid).map_err(write_error)?;
+    io::write_elevation(ele, coords, &ta.ulid).map_err(write_error)?;
+    write_track_analysis(&ta).map_err(write_error)?;
+    write_geojson(&geojson, &ta.ulid).map_err(write_error)?;
+    io::end_write(&ta.ulid).map_err(write_error)?;
+    Ok(ta)
+}
+
+/// Replaces the elevation profile and the elevation stats of the track with
+/// those of the source. Fails if the device did not record the source.
+pub fn set_elevation_source(ulid: &String, source: ElevationSource) -> Result<TrackAnalysis, MaplineError> {
+    let mut ta = io::read_track_analysis(ulid).map_err(|e| MaplineError::FileError(e.to_string()))?;
+    let gpx = io::read_gpx(ulid).ok_or_else(|| MaplineError::FileError("record.gpx could not be read".to_string()))?;
+    let sensor_data = io::read_sensor_data(ulid).unwrap_or_default();
+    // tracks set to gps before fit points were known to carry the barometric
+    // altitude keep their source
+    if source == ElevationSource::Gps && ta.elevation_source != Some(source) && !elevation::has_gps_channel(&gpx, &sensor_data) {
+        return Err(MaplineError::CouldNotLoadElevation);
+    }
+    let (ele, ele_gain, ele_loss, ele_max, ele_min, coords) = elevation::from_source(source, &gpx, &sensor_data, &ta.pauses)?;
+    ta.ele_gain = Some(ele_gain);
+    ta.ele_loss = Some(ele_loss);
+    ta.ele_max = Some(ele_max);
+    ta.ele_min = Some(ele_min);
+    ta.elevation_source = Some(source);
+    let write_error = |e: std::io::Error| MaplineError::WriteError(e.to_string());
+    io::begin_write(ulid).map_err(write_error)?;
+    io::write_elevation(ele, coords, ulid).map_err(write_error)?;
+    write_track_analysis(&ta).map_err(write_error)?;
+    io::end_write(ulid).map_err(write_error)?;
+    Ok(ta)
+}
+
+/// Profiles of all elevation sources of the track, for comparison
+pub fn elevation_profiles(ulid: &String) -> Option<ElevationProfiles> {
+    let ta = io::read_track_analysis(ulid).ok()?;
+    let gpx = io::read_gpx(ulid)?;
+    let sensor_data = io::read_sensor_data(ulid).unwrap_or_default();
+    Some(elevation::profiles(&gpx, &sensor_data, &ta.pauses))
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use serde::{Serialize, Deserialize};
+use tauri::Window;
+
+use crate::{import, paths, source};
+use crate::errors::MaplineError;
+use crate::settings::Settings;
+
+/// Time to wait after the last change of a file, so files that are still being
+/// copied are complete
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+pub enum WatchCommand {
+    AddPath(PathBuf),
+    Rescan,
+    Changed(PathBuf),
+}
+
+/// Managed by tauri, used to send commands to the watcher thread
+pub struct WatcherState(pub Mutex<Sender<WatchCommand>>);
+
+/// File of an import path that was already looked at
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SeenFile {
+    pub size: u64,
+    pub modified: Option<u64>, // unix timestamp
+    pub checksum: String,
+}
+
+/// Files of the import paths by path. Files with unchanged size and modification
+/// time are not read again on a rescan.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SeenFiles {
+    pub files: HashMap<String, SeenFile>,
+}
+
+impl SeenFiles {
+    pub fn load() -> SeenFiles {
+        match fs::read_to_string(paths::seen_files()) {
+            Ok(s) => serde_json::from_str(&s.as_str()).unwrap_or_default(),
+            Err(_e) => SeenFiles::default(),
+        }
+    }
+
+    fn write(&self) -> Result<(), std::io::Error> {
+        crate::io::write_atomic(&paths::seen_files(), serde_json::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn contains_checksum(&self, checksum: &String) -> bool {
+        self.files.values().any(|f| &f.checksum == checksum)
+    }
+}
+
+/// Starts the thread watching the import paths of the settings. All scans of
+/// import paths are done by this thread.
+pub fn start(window: Window) -> Sender<WatchCommand> {
+    let (sender, receiver) = channel::<WatchCommand>();
+    let fs_sender = sender.clone();
+    std::thread::spawn(move || {
+        let mut watcher = match notify::recommended_watcher(move |res: notify::Result<Event>| {
+            if let Ok(event) = res {
+                if let EventKind::Create(_) | EventKind::Modify(_) = event.kind {
+                    for p in event.paths {
+                        fs_sender.send(WatchCommand::Changed(p)).ok();
+                    }
+                }
+            }
+        }) {
+            Ok(w) => w,
+            Err(e) => { println!("Could not start import path watcher: {:?}", e); return; },
+        };
+        let mut seen = SeenFiles::load();
+        // paths of the last settings read, kept if the settings can not be read
+        let mut import_paths: Vec<PathBuf> = match Settings::load() {
+            Ok(s) => s.import_paths,
+            Err(e) => { println!("Could not read the import paths: {:?}", e); vec![] },
+        };
+        for p in &import_paths {
+            if let Err(e) = watcher.watch(p, RecursiveMode::NonRecursive) {
+                println!("Could not watch {:?}: {:?}", p, e);
+            }
+            scan_dir(p, &mut seen, &window);
+        }
+
+        // changed files by the time they are settled, every change of a file
+        // restarts its settle time
+        let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+        loop {
+            let command = match pending.values().min() {
+                Some(settled) => match receiver.recv_timeout(settled.saturating_duration_since(Instant::now())) {
+                    Ok(c) => Some(c),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                },
+                None => match receiver.recv() {
+                    Ok(c) => Some(c),
+                    Err(_e) => return,
+                },
+            };
+            match command {
+                Some(WatchCommand::AddPath(p)) => {
+                    if let Err(e) = watcher.watch(&p, RecursiveMode::NonRecursive) {
+                        println!("Could not watch {:?}: {:?}", p, e);
+                    }
+                    scan_dir(&p, &mut seen, &window);
+                    import_paths.push(p);
+                }
+                Some(WatchCommand::Rescan) => {
+                    match Settings::load() {
+                        Ok(s) => import_paths = s.import_paths,
+                        Err(e) => println!("Could not read the import paths, scanning the previous ones: {:?}", e),
+                    }
+                    for p in &import_paths {
+                        scan_dir(p, &mut seen, &window);
+                    }
+                }
+                Some(WatchCommand::Changed(p)) => {
+                    pending.insert(p, Instant::now() + SETTLE_TIME);
+                }
+                None => (),
+            }
+            let now = Instant::now();
+            let settled: Vec<PathBuf> = pending.iter()
+                .filter(|(_, s)| **s <= now)
+                .map(|(p, _)| p.clone())
+                .collect();
+            for p in settled {
+                pending.remove(&p);
+                scan_file(&p, &mut seen, &window);
+            }
+        }
+    });
+    sender
+}
+
+fn scan_dir(dir: &Path, seen: &mut SeenFiles, window: &Window) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => { println!("Could not read import path {:?}: {}", dir, e); return; },
+    };
+    for entry in entries.flatten() {
+        scan_file(&entry.path(), seen, window);
+    }
+}
+
+/// Imports the file if it was not seen before or has changed since. Files that
+/// could not be imported are not recorded, so they are tried again on the next scan.
+fn scan_file(path: &Path, seen: &mut SeenFiles, window: &Window) {
+    let metadata = match fs::metadata(path) {
+        Ok(m) if m.is_file() => m,
+        _ => return,
+    };
+    let key = path.to_string_lossy().to_string();
+    let modified = metadata.modified().ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+    if let Some(s) = seen.files.get(&key) {
+        if s.size == metadata.len() && s.modified == modified {
+            return;
+        }
+    }
+
+    let data = match import::read_file(&path.to_path_buf()) {
+        Ok(d) => d,
+        Err(e) => { println!("{:?}", e); return; },
+    };
+    let checksum = source::checksum(&data);
+    // same content seen under another path (e.g. copied file)
+    if !seen.contains_checksum(&checksum) {
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        match import::data(data, file_name, &import::extension(path), false) {
+            Ok(file) => for ta in file.written { window.emit("track_import", ta).ok(); },
+            Err(MaplineError::TrackAlreadyImported(_)) => (),
+            Err(e) => {
+                println!("Could not import {:?}: {:?}", path, e);
+                return;
+            }
+        }
+    }
+    seen.files.insert(key, SeenFile { size: metadata.len(), modified, checksum });
+    if let Err(e) = seen.write() {
+        println!("Could not write the seen files: {:?}", e);
+    }
+}
@@ -53,9 +53,148 @@ function editRow() {
         }
         activityField.value = activityDefault;
         nameField.value = nameDefault;
+        if (selected_rows.length == 1) {
+            let ulid = selected_rows[0];
+            invoke('load_history', { ulid: ulid }).then(showHistory);
+            showSummarySource(row_objects[ulid]);
+            invoke('load_laps', { ulid: ulid }).then(showLaps);
+            invoke('load_sensor_data', { ulid: ulid }).then(showSensors);
+        } else {
+            document.getElementById("track-history").style.display = "none";
+            document.getElementById("track-summary-source").style.display = "none";
+            document.getElementById("track-laps").style.display = "none";
+            document.getElementById("track-sensors").style.display = "none";
+        }
         setEditRowOverlay();
 }
 
+// the choice is only offered for tracks with totals of the recording device
+function showSummarySource(entry) {
+    let wrapper = document.getElementById("track-summary-source");
+    if (entry.device_summary == null) {
+        wrapper.style.display = "none";
+        return;
+    }
+    wrapper.style.display = "";
+    document.getElementById("summary-source-input").value = entry.summary_source;
+}
+
+function setSummarySource(source) {
+    let ulid = selected_rows[0];
+    invoke('set_summary_source', { ulid: ulid, source: source })
+    .then(ta => {
+        if (ta == null) {
+            return;
+        }
+        row_objects[ulid] = ta;
+        reloadTable();
+        return invoke('load_history', { ulid: ulid }).then(showHistory);
+    });
+}
+
+// laps recorded by the device, laps without points (e.g. pool swimming) only have totals
+function showLaps(laps) {
+    let wrapper = document.getElementById("track-laps");
+    let body = document.getElementById("track-laps-body");
+    body.innerHTML = "";
+    if (laps == null || laps.length == 0) {
+        wrapper.style.display = "none";
+        return;
+    }
+    wrapper.style.display = "";
+    let value = (v, digits) => v == null ? "-" : v.toFixed(digits);
+    laps.forEach((lap, i) => {
+        let row = body.insertRow();
+        row.insertCell(0).innerHTML = i + 1;
+        row.insertCell(1).innerHTML = lap.duration_sec == null ? "-" : formatDuration(lap.duration_sec);
+        row.insertCell(2).innerHTML = lap.distance == null ? "-" : (lap.distance / 1000).toFixed(2);
+        row.insertCell(3).innerHTML = value(lap.avg_vel, 2);
+        row.insertCell(4).innerHTML = value(lap.avg_heart_rate, 0);
+        if (lap.start_index == null) {
+            row.style.opacity = "0.5";
+        }
+    });
+}
+
+// average and maximum of every recorded sensor
+function showSensors(result) {
+    let wrapper = document.getElementById("track-sensors");
+    let list = document.getElementById("track-sensors-list");
+    list.innerHTML = "";
+    if (result == null) {
+        wrapper.style.display = "none";
+        return;
+    }
+    let data = result[1];
+    let sensors = [
+        ["heart rate", data.heart_rate, "bpm"],
+        ["cadence", data.cadence, "rpm"],
+        ["power", data.power, "W"],
+        ["temperature", data.temperature, "°C"],
+    ];
+    sensors.forEach(([name, values, unit]) => {
+        let recorded = values.filter(v => v != null);
+        if (recorded.length == 0) {
+            return;
+        }
+        let avg = recorded.reduce((a, b) => a + b, 0) / recorded.length;
+        let item = document.createElement("li");
+        item.innerHTML = name + ": avg " + Math.round(avg) + ", max " + Math.round(Math.max(...recorded)) + " " + unit;
+        list.appendChild(item);
+    });
+    wrapper.style.display = list.children.length == 0 ? "none" : "";
+}
+
+function formatDuration(seconds) {
+    let s = Math.round(seconds);
+    let pad = n => String(n).padStart(2, "0");
+    return Math.floor(s / 3600) + ":" + pad(Math.floor(s / 60) % 60) + ":" + pad(s % 60);
+}
+
+// operations of a single track, undone operations are greyed out
+function showHistory(journal) {
+    let history = document.getElementById("track-history");
+    let list = document.getElementById("track-history-list");
+    list.innerHTML = "";
+    if (journal == null) {
+        history.style.display = "none";
+        return;
+    }
+    history.style.display = "";
+    journal.entries.forEach((entry, i) => {
+        let item = document.createElement("li");
+        item.innerHTML = new Date(1000 * entry.time).toLocaleString() + ": " + entry.operation.type.replaceAll("_", " ");
+        if (i >= journal.position) {
+            item.style.opacity = "0.5";
+        }
+        list.appendChild(item);
+    });
+    document.getElementById("undo-row-edit").disabled = journal.position == 0;
+    document.getElementById("redo-row-edit").disabled = journal.position == journal.entries.length;
+}
+
+function undoEditRow() {
+    let ulid = selected_rows[0];
+    invoke('undo_track', { ulid: ulid })
+    .then(journal => {
+        removeTrack(ulid);
+        reloadTable();
+        return invoke('load_history', { ulid: ulid });
+    })
+    .then(showHistory);
+}
+
+function redoEditRow() {
+    let ulid = selected_rows[0];
+    invoke('redo_track', { ulid: ulid })
+    .then(journal => {
+        removeTrack(ulid);
+        reloadTable();
+        return invoke('load_history', { ulid: ulid });
+    })
+    .then(showHistory);
+}
+
 function cancelEditRow() {
     setNoOverlay();
 }
@@ -180,6 +319,10 @@ function joinRows() {
         reloadTable();
         setNoOverlay();
     })
+    .catch(error => {
+        setNoOverlay();
+        alert("The tracks could not be joined: " + error);
+    });
 }
 
 async function recalculateRows() {
@@ -199,13 +342,51 @@ async function recalculateRows() {
     setNoOverlay();
 }
 
+// tracks without the chosen source (e.g. no recorded altitude) keep their profile
+async function setElevationSourceRows(select) {
+    let source = select.value;
+    select.value = "";
+    if (source === "") {
+        return;
+    }
+    var pos = 0;
+    var failed = 0;
+    document.getElementById('loading-bar').style.width = "0%";
+    document.getElementById('loading-text').innerHTML = "calculating... (" + pos + " / " + selected_rows.length + ")";
+    setLoadingInfoOverlay();
+    for (ulid of selected_rows) {
+        await invoke('set_elevation_source', { ulid: ulid, source: source })
+        .then(response => {
+            if (response == null) {
+                failed += 1;
+            }
+            pos += 1;
+            document.getElementById('loading-text').innerHTML = "calculating... (" + pos + " / " + selected_rows.length + ")";
+            document.getElementById('loading-bar').style.width = pos / selected_rows.length * 100 + "%";
+        });
+    }
+    reloadTable();
+    setNoOverlay();
+    if (failed > 0) {
+        alert(failed + " tracks have no " + source + " elevation.");
+    }
+}
+
+// tracks are moved to the trash, in the trash they are deleted for good
 function deleteEditRow() {
+    let purge = currentTableView === TableView.Trash;
+    let question = purge
+        ? "Delete " + selected_rows.length + " tracks permanently?"
+        : "Move " + selected_rows.length + " tracks to the trash?";
+    if (!confirm(question)) {
+        return;
+    }
     var pos = 0;
     document.getElementById('loading-bar').style.width = "0%";
     document.getElementById('loading-text').innerHTML = "deleting... (" + pos + " / " + selected_rows.length + ")";
     setLoadingInfoOverlay();
     for (r of selected_rows) {
-        invoke('delete_track', { ulid : r })
+        invoke(purge ? 'purge_track' : 'delete_track', { ulid : r })
         .then(response => {
             removeTrack(r)
             delete elevationCoords[r];
@@ -216,4 +397,24 @@ function deleteEditRow() {
     }
     reloadTable();
     setNoOverlay();
-}
\ No newline at end of file
+}
+
+// archives the selected tracks, in the archived view they are unarchived
+async function archiveRows() {
+    let archived = currentTableView !== TableView.Archived;
+    for (ulid of selected_rows) {
+        await invoke('set_archived', { ulid: ulid, archived: archived });
+        removeTrack(ulid);
+    }
+    selected_rows = [];
+    reloadTable();
+}
+
+async function restoreRows() {
+    for (ulid of selected_rows) {
+        await invoke('restore_track', { ulid: ulid });
+        removeTrack(ulid);
+    }
+    selected_rows = [];
+    reloadTable();
+}
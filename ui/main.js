@@ -32,6 +32,13 @@ const OverlayState = {
 };
 var currentOverlayState = OverlayState.None;
 
+const TableView = {
+    Tracks: 'tracks',
+    Archived: 'archived',
+    Trash: 'trash',
+}
+var currentTableView = TableView.Tracks;
+
 window.onload = init;
 
 function init() {
@@ -50,6 +57,114 @@ listen("track_import", ev => {
     add_to_table(ev.payload, true);
 });
 
+// edits are written in the background, the table is reloaded once they are saved
+listen("track_changed", ev => {
+    reloadTable();
+});
+
+listen("route_import", ev => {
+    console.log("planned route imported: " + (ev.payload.name == null ? ev.payload.ulid : ev.payload.name));
+});
+
+listen("import_progress", ev => {
+    let p = ev.payload;
+    let file = p.file.split(/[\\/]/).pop();
+    document.getElementById("import-progress-wrapper").style.display = "flex";
+    document.getElementById("import-progress-text").innerHTML = "Importing " + (p.done + 1) + " of " + p.total + ": " + file + " (" + p.stage + ")";
+});
+
+listen("csv_import", async ev => {
+    let paths = ev.payload;
+    let result = await invoke("load_csv_columns", { path: paths[0] });
+    if (result == null) {
+        alert("The csv file could not be read.");
+        return;
+    }
+    let [headers, columns] = result;
+    let mapColumn = (label, current) => {
+        let value = prompt("Column for " + label + " (" + headers.join(", ") + "), empty for automatic detection:",
+            current == null ? "" : current);
+        return value == null || value.trim() == "" ? null : value.trim();
+    };
+    columns = {
+        lat: mapColumn("latitude", columns.lat),
+        lon: mapColumn("longitude", columns.lon),
+        time: mapColumn("time", columns.time),
+        ele: mapColumn("elevation", columns.ele),
+    };
+    invoke("import_csv", { paths: paths, columns: columns });
+});
+
+function cancelImport() {
+    invoke("cancel_import");
+}
+
+listen("import_report", ev => {
+    document.getElementById("import-progress-wrapper").style.display = "none";
+    let files = ev.payload.files;
+    let imported = files.filter(f => f.outcome == "imported");
+    let failed = files.filter(f => f.outcome == "failed");
+    // only the duplicate tracks of a file are imported again, not the whole file
+    let duplicates = files.map(f => ({
+        path: f.path,
+        archive: f.archive,
+        parts: f.parts.filter(p => p.outcome == "duplicate").map(p => p.part),
+    })).filter(f => f.parts.length > 0);
+    let lines = [];
+    files.forEach(f => {
+        if (f.parts.length > 0) {
+            f.parts.forEach(p => lines.push(f.path + " (track " + (p.part + 1) + "): " + import_outcome_text(p)));
+        } else if (f.outcome != "imported") {
+            lines.push(f.path + ": " + import_outcome_text(f));
+        }
+    });
+    if (lines.length == 0) {
+        return;
+    }
+    alert(imported.length + " of " + files.length + " files imported.\n\n" + lines.join("\n"));
+    if (duplicates.length > 0 && confirm("Import the duplicate tracks of " + duplicates.length + " files anyway?")) {
+        retryImport(duplicates, true);
+    }
+    if (failed.length > 0 && confirm("Retry the " + failed.length + " failed files?")) {
+        retryImport(failed.map(f => ({ path: f.path, archive: f.archive, parts: null })), false);
+    }
+});
+
+// entries of an archive are imported from their archive again, parts are the
+// indices of the tracks of a file to import (all tracks if null)
+function retryImport(files, force) {
+    let toRetry = f => ({ path: f.path, parts: f.parts });
+    let plain = files.filter(f => f.archive == null).map(toRetry);
+    if (plain.length > 0) {
+        invoke("retry_import", { files: plain, force: force });
+    }
+    let archives = [...new Set(files.filter(f => f.archive != null).map(f => f.archive))];
+    archives.forEach(archive => {
+        let entries = files.filter(f => f.archive == archive).map(toRetry);
+        invoke("retry_archive_import", { archive: archive, entries: entries, force: force });
+    });
+}
+
+function import_outcome_text(file) {
+    switch (file.outcome) {
+        case "duplicate":
+            let c = file.candidate;
+            let name = c.name == null ? c.ulid : c.name;
+            let start = c.start_time == null ? "-" : c.start_time;
+            return "duplicate of " + name + " (" + start + ", " + Math.round(c.similarity * 100) + "% similar)";
+        case "not_an_activity":
+            return "not an activity";
+        case "parse_error":
+            return "could not be read (" + file.message + ")";
+        case "unsupported":
+            return "unsupported format";
+        case "cancelled":
+            return "cancelled";
+        default:
+            return "failed (" + file.message + ")";
+    }
+}
+
 function initContentResize() {
     let vSeparator = document.getElementById("ver-content-separator");
     vSeparator.addEventListener("mousedown", (e) => {
@@ -141,29 +256,85 @@ function list_gpx_files() {
 function reloadTable() {
     table_body = document.getElementById("gpxTableBody");
     row_objects = {};
-    invoke('load_track_analysis')
-        .then((response) => {
-            while(table_body.rows.length > 0) {
-                table_body.deleteRow(0);
-            }
-            response.forEach( entry => {
-                add_to_table(entry, false);
-            });
-            sortRowsDate(document.getElementById("gpx-table"), 0, row_objects);
-        });
+    let request;
+    if (currentTableView === TableView.Archived) {
+        request = invoke('filter_tracks', { filter: { archived: true } });
+    } else if (currentTableView === TableView.Trash) {
+        request = invoke('load_trash');
+    } else {
+        request = invoke('load_track_analysis');
+    }
+    request.then(fillTable);
+}
+
+// archived tracks are only listed in their own view, trashed tracks only in the trash
+function setTableView(view) {
+    currentTableView = view;
+    document.getElementById("table-filter").value = "";
+    document.getElementById("table-button-archive").innerHTML = view === TableView.Archived ? "Unarchive" : "Archive";
+    document.getElementById("table-button-archive").style.display = view === TableView.Trash ? "none" : "";
+    document.getElementById("table-button-restore").style.display = view === TableView.Trash ? "" : "none";
+    reloadTable();
 }
 
+// matches the name, tags and notes of the tracks, an empty filter shows all tracks.
+// The search includes archived tracks.
+function filterTable(text) {
+    if (currentTableView === TableView.Trash || text.trim() === "") {
+        reloadTable();
+        return;
+    }
+    table_body = document.getElementById("gpxTableBody");
+    row_objects = {};
+    let archived = currentTableView === TableView.Archived ? true : null;
+    invoke('filter_tracks', { filter: { text: text.trim(), archived: archived } })
+        .then(fillTable);
+}
+
+function fillTable(response) {
+    while(table_body.rows.length > 0) {
+        table_body.deleteRow(0);
+    }
+    response.forEach( entry => {
+        add_to_table(entry, false);
+    });
+    sortRowsDate(document.getElementById("gpx-table"), 0, row_objects);
+}
+
+
+// with the device as summary source, the totals of the device replace the computed ones
+function summaryOf(entry) {
+    let d = entry.device_summary;
+    if (entry.summary_source !== "Device" || d == null) {
+        return entry;
+    }
+    let pick = (device, computed) => device == null ? computed : device;
+    return Object.assign({}, entry, {
+        distance: pick(d.distance, entry.distance),
+        avg_vel: pick(d.avg_vel, entry.avg_vel),
+        time_moving: pick(d.time_moving, entry.time_moving),
+        time_total: pick(d.time_total, entry.time_total),
+        ele_gain: pick(d.ele_gain, entry.ele_gain),
+        ele_loss: pick(d.ele_loss, entry.ele_loss),
+    });
+}
 
 function add_to_table(entry, sort) {
     let row = table_body.insertRow();
     row_objects[entry.ulid] = entry;
+    let summary = summaryOf(entry);
     let ulid = row.insertCell(0);
     ulid.innerHTML = entry.ulid;
     ulid.style.display = "none"; // used to identify row but don't display
     let time = row.insertCell(1);
-    let datetime = new Date(entry.start_time);
-    // time.innerHTML = datetime.toLocaleDateString();
-    time.innerHTML = datetime.toLocaleDateString().replaceAll('/', '-');
+    if (entry.start_time === null) {
+        // track without timestamps
+        time.innerHTML = "-";
+    } else {
+        let datetime = new Date(entry.start_time);
+        // time.innerHTML = datetime.toLocaleDateString();
+        time.innerHTML = datetime.toLocaleDateString().replaceAll('/', '-');
+    }
     time.style.textAlign = "left";
     let type = row.insertCell(2);
     type.innerHTML = entry._type;
@@ -172,22 +343,27 @@ function add_to_table(entry, sort) {
     name.innerHTML = entry.name;
     name.style.textAlign = "left";
     let distance = row.insertCell(4);
-    distance.innerHTML = (entry.distance / 1000).toFixed(2);
+    distance.innerHTML = (summary.distance / 1000).toFixed(2);
     let avgVel = row.insertCell(5);
-    if (entry.avg_vel === null) {
-        entry.avg_vel = 0.0;
-    }
-    avgVel.innerHTML = entry.avg_vel.toFixed(2);
+    avgVel.innerHTML = summary.avg_vel === null ? (0.0).toFixed(2) : summary.avg_vel.toFixed(2);
     let timeMoving = row.insertCell(6);
     // timeMoving.innerHTML = entry.time_moving;
     // timeMoving.innerHTML = new Date(1000 * entry.time_moving - 3600000).toTimeString().substring(0, 8);
-    timeMoving.innerHTML = new Date(1000 * entry.time_moving - 3600000).toLocaleTimeString();
+    if (summary.time_moving === null) {
+        timeMoving.innerHTML = "-";
+    } else {
+        timeMoving.innerHTML = new Date(1000 * summary.time_moving - 3600000).toLocaleTimeString();
+    }
     let timeTotal = row.insertCell(7);
-    timeTotal.innerHTML = new Date(1000 * entry.time_total - 3600000).toLocaleTimeString();
+    if (summary.time_total === null) {
+        timeTotal.innerHTML = "-";
+    } else {
+        timeTotal.innerHTML = new Date(1000 * summary.time_total - 3600000).toLocaleTimeString();
+    }
     let eleGain = row.insertCell(8);
-    eleGain.innerHTML = Math.round(entry.ele_gain);
+    eleGain.innerHTML = Math.round(summary.ele_gain);
     let eleLoss = row.insertCell(9);
-    eleLoss.innerHTML = Math.round(entry.ele_loss);
+    eleLoss.innerHTML = Math.round(summary.ele_loss);
     let maxEle = row.insertCell(10);
     maxEle.innerHTML = Math.round(entry.ele_max);
     let minEle = row.insertCell(11);
@@ -293,10 +469,31 @@ function toggleRowSelection(entry) {
             }
         });
 
+        if (entry.planned_route != null) {
+            showRouteComparison(entry);
+        }
+
         addTrackIcons(entry);
     }
 }
 
+// planned vs. actual values are shown as tooltip of the selected row
+function showRouteComparison(entry) {
+    invoke('compare_route', { ulid: entry.ulid })
+    .then((c) => {
+        if (c == null) {
+            return;
+        }
+        let text = "Planned: " + (c.planned_distance / 1000).toFixed(2) + " km, " + Math.round(c.planned_ele_gain) + " m up"
+            + "\nActual: " + (c.actual_distance / 1000).toFixed(2) + " km, " + Math.round(c.actual_ele_gain) + " m up";
+        table_body.querySelectorAll("tr").forEach(row => {
+            if (row.querySelectorAll("td")[0].innerHTML == entry.ulid) {
+                row.title = text;
+            }
+        });
+    });
+}
+
 var currentMapBounds;
 function fitMapBounds() {
     map.fitBounds(currentMapBounds, {